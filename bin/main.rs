@@ -1,7 +1,15 @@
 #![deny(warnings)]
 
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
 use clap::Parser;
-use rxqlite::{init_example_raft_node, start_example_raft_node};
+use rxqlite::{
+    init_example_raft_node, start_example_raft_node, CompactionPolicy, RaftTiming, ReadMode,
+    SnapshotCompression, WriteMode,
+};
+use serde::{de, Deserialize, Deserializer};
 //use tracing_subscriber::EnvFilter;
 use rxqlite_common::RSQliteNodeTlsConfig;
 //use openraft::NodeId;
@@ -9,13 +17,27 @@ use rxqlite_common::RSQliteNodeTlsConfig;
 #[derive(Parser, Clone, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Opt {
-    #[clap(long)]
-    pub id: u64,
+    /// The id of the node, required here or in the config file.
+    #[clap(long, env = "RXQLITED_ID")]
+    pub id: Option<u64>,
+
+    /// A toml file holding the settings of this node. The flags, then the environment
+    /// variables, override it.
+    #[clap(long, env = "RXQLITED_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The directory of the node, data-<id> in the current directory by default.
+    #[clap(long, env = "RXQLITED_DATA_DIR")]
+    data_dir: Option<PathBuf>,
 
-    #[clap(long)]
+    /// On restart, replaces the address the node was initialized with, the other nodes
+    /// learning it once it joins the leader.
+    #[clap(long, env = "RXQLITED_HTTP_ADDR")]
     pub http_addr: Option<String>,
 
-    #[clap(long)]
+    /// On restart, replaces the address the node was initialized with, the other nodes
+    /// learning it once it joins the leader.
+    #[clap(long, env = "RXQLITED_RPC_ADDR")]
     pub rpc_addr: Option<String>,
 
     #[clap(long,action = clap::ArgAction::SetTrue)]
@@ -24,28 +46,226 @@ pub struct Opt {
     #[clap(long, action = clap::ArgAction::Append)]
     member: Vec<String>, // id;http_addr;rpc_addr
 
-    #[clap(long)]
+    #[clap(long, env = "RXQLITED_KEY_PATH")]
     key_path: Option<String>,
 
-    #[clap(long)]
+    #[clap(long, env = "RXQLITED_CERT_PATH")]
     cert_path: Option<String>,
 
-    #[clap(long,action = clap::ArgAction::SetTrue)]
+    #[clap(
+        long,
+        env = "RXQLITED_ACCEPT_INVALID_CERTIFICATES",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
     accept_invalid_certificates: Option<bool>,
 
-    #[clap(long,action = clap::ArgAction::SetTrue)]
+    /// Don't encrypt the database and the logs with the key of the tls configuration, only
+    /// read on initialization.
+    #[clap(
+        long,
+        env = "RXQLITED_NO_DATABASE_ENCRYPTION",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
     no_database_encryption: Option<bool>,
 
-    #[clap(long)]
+    #[clap(long, env = "RXQLITED_NOTIFICATIONS_ADDR")]
     notifications_addr: Option<String>,
-    
+
+    /// How writes are replicated: statements (the default) or changeset, only read on
+    /// initialization.
+    #[clap(long, env = "RXQLITED_WRITE_MODE")]
+    write_mode: Option<WriteMode>,
+
+    /// How sql-consistent reads check that this node is the leader: quorum (the default) or
+    /// lease.
+    #[clap(long, env = "RXQLITED_READ_MODE")]
+    read_mode: Option<ReadMode>,
+
+    /// Forward the sql-consistent reads this node receives as a follower to the leader,
+    /// rather than answering them with the address of the leader.
+    #[clap(
+        long,
+        env = "RXQLITED_FORWARD_READS",
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    forward_reads: Option<bool>,
+
+    /// How the snapshots this node builds are compressed: none (the default), lz4, zstd or
+    /// zstd:<level>.
+    #[clap(long, env = "RXQLITED_SNAPSHOT_COMPRESSION")]
+    snapshot_compression: Option<SnapshotCompression>,
+
+    /// How often the leader sends heartbeats, in milliseconds.
+    #[clap(long, env = "RXQLITED_HEARTBEAT_INTERVAL")]
+    heartbeat_interval: Option<u64>,
+
+    /// How long a follower waits for the leader before campaigning, at least, in
+    /// milliseconds.
+    #[clap(long, env = "RXQLITED_ELECTION_TIMEOUT_MIN")]
+    election_timeout_min: Option<u64>,
+
+    /// How long a follower waits for the leader before campaigning, at most, in
+    /// milliseconds.
+    #[clap(long, env = "RXQLITED_ELECTION_TIMEOUT_MAX")]
+    election_timeout_max: Option<u64>,
+
+    /// How long the leader answers reads on its own in lease read mode once a quorum
+    /// acknowledged it, in milliseconds: 90% of the minimum election timeout less the
+    /// maximum clock drift by default.
+    #[clap(long, env = "RXQLITED_LEASE_DURATION")]
+    lease_duration: Option<u64>,
+
+    /// How much sooner than on the leader an election timeout may elapse on a follower,
+    /// in milliseconds, which the lease of the leader leaves out.
+    #[clap(long, env = "RXQLITED_MAX_CLOCK_DRIFT")]
+    max_clock_drift: Option<u64>,
+
+    /// Build a snapshot once this many logs were applied since the last one, 0 to disable.
+    #[clap(long, env = "RXQLITED_SNAPSHOT_LOGS_SINCE_LAST")]
+    snapshot_logs_since_last: Option<u64>,
+
+    /// Build a snapshot once the logs appended since the last one take this many bytes,
+    /// 0 to disable.
+    #[clap(long, env = "RXQLITED_SNAPSHOT_LOG_BYTES")]
+    snapshot_log_bytes: Option<u64>,
+
+    /// Build a snapshot once this many seconds passed since the last one, 0 to disable.
+    #[clap(long, env = "RXQLITED_SNAPSHOT_INTERVAL_SECS")]
+    snapshot_interval_secs: Option<u64>,
+
+    /// How many of the logs included in the last snapshot are kept.
+    #[clap(long, env = "RXQLITED_MAX_IN_SNAPSHOT_LOG_TO_KEEP")]
+    max_in_snapshot_log_to_keep: Option<u64>,
+
+    /// The fewest logs purged at once.
+    #[clap(long, env = "RXQLITED_PURGE_BATCH_SIZE")]
+    purge_batch_size: Option<u64>,
+
+    /// What the node logs, as the directives of RUST_LOG.
+    #[clap(long, env = "RUST_LOG")]
+    log_filter: Option<String>,
+
     #[clap(long,action = clap::ArgAction::SetTrue)]
     test_node: Option<bool>,
 
 }
 
+/// The settings of a node read from the file given by `--config`, named after the flags.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    id: Option<u64>,
+    data_dir: Option<PathBuf>,
+    http_addr: Option<String>,
+    rpc_addr: Option<String>,
+    notifications_addr: Option<String>,
+    tls: TlsSection,
+    no_database_encryption: Option<bool>,
+    #[serde(deserialize_with = "from_flag")]
+    write_mode: Option<WriteMode>,
+    #[serde(deserialize_with = "from_flag")]
+    read_mode: Option<ReadMode>,
+    forward_reads: Option<bool>,
+    #[serde(deserialize_with = "from_flag")]
+    snapshot_compression: Option<SnapshotCompression>,
+    raft: RaftTiming,
+    compaction: CompactionPolicy,
+    log: LogSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    key_path: Option<String>,
+    cert_path: Option<String>,
+    accept_invalid_certificates: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+    filter: Option<String>,
+}
+
+/// Reads a setting of the config file written as the value of its flag.
+fn from_flag<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let config = std::fs::read_to_string(path).map_err(|err| {
+            anyhow::anyhow!("couldn't read config file {}: {}", path.display(), err)
+        })?;
+        toml::from_str(&config).map_err(|err| {
+            anyhow::anyhow!("couldn't parse config file {}: {}", path.display(), err)
+        })
+    }
+}
+
+impl Opt {
+    /// The raft timing of the config file, with the flags given overriding it.
+    fn raft_timing(&self, config: &ConfigFile) -> RaftTiming {
+        let mut timing = config.raft;
+        if let Some(interval) = self.heartbeat_interval {
+            timing.heartbeat_interval = interval;
+        }
+        if let Some(timeout) = self.election_timeout_min {
+            timing.election_timeout_min = timeout;
+        }
+        if let Some(timeout) = self.election_timeout_max {
+            timing.election_timeout_max = timeout;
+        }
+        if let Some(lease) = self.lease_duration {
+            timing.lease_duration = Some(lease);
+        }
+        if let Some(drift) = self.max_clock_drift {
+            timing.max_clock_drift = drift;
+        }
+        timing
+    }
+
+    /// The compaction policy of the config file, with the flags given overriding it.
+    fn compaction_policy(&self, config: &ConfigFile) -> CompactionPolicy {
+        let mut policy = config.compaction;
+        if let Some(logs) = self.snapshot_logs_since_last {
+            policy.snapshot_logs_since_last = logs;
+        }
+        if let Some(bytes) = self.snapshot_log_bytes {
+            policy.snapshot_log_bytes = bytes;
+        }
+        if let Some(secs) = self.snapshot_interval_secs {
+            policy.snapshot_interval_secs = secs;
+        }
+        if let Some(logs) = self.max_in_snapshot_log_to_keep {
+            policy.max_in_snapshot_log_to_keep = logs;
+        }
+        if let Some(size) = self.purge_batch_size {
+            policy.purge_batch_size = size;
+        }
+        policy
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // Parse the parameters passed by arguments.
+    let options = Opt::parse();
+
+    let config = match &options.config {
+        Some(path) => ConfigFile::read(path)?,
+        None => ConfigFile::default(),
+    };
     
     // Setup the logger
     /*
@@ -59,6 +279,10 @@ async fn main() -> anyhow::Result<()> {
         .init();
     */
 
+    let env_filter = match options.log_filter.as_ref().or(config.log.filter.as_ref()) {
+        Some(filter) => tracing_subscriber::EnvFilter::new(filter),
+        None => tracing_subscriber::EnvFilter::default(),
+    };
     let subscriber = tracing_subscriber::fmt()
         // Use a more compact, abbreviated log format
         .compact()
@@ -71,24 +295,49 @@ async fn main() -> anyhow::Result<()> {
         // Don't display the event's target (module path)
         .with_target(true)
         //.with_max_level(tracing::Level::TRACE)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_env_filter(env_filter)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    // Parse the parameters passed by arguments.
-    let options = Opt::parse();
-
     if let Some(true) = options.test_node {
       rxqlite_common::IN_TEST.store(true,rxqlite_common::Ordering::Relaxed);
     }
 
-    let base_path = std::path::PathBuf::from(format!("data-{}", options.id));
+    let id = match options.id.or(config.id) {
+        Some(id) => id,
+        None => {
+            return Err(anyhow::anyhow!(
+                "the id of the node must be given with --id or in the config file"
+            ))
+        }
+    };
+    let raft_timing = options.raft_timing(&config);
+    let compaction_policy = options.compaction_policy(&config);
+    let http_addr = options.http_addr.or(config.http_addr);
+    let rpc_addr = options.rpc_addr.or(config.rpc_addr);
+    let notifications_addr = options.notifications_addr.or(config.notifications_addr);
+    let read_mode = options.read_mode.or(config.read_mode).unwrap_or_default();
+    let forward_reads = options.forward_reads.or(config.forward_reads).unwrap_or(false);
+    let snapshot_compression = options
+        .snapshot_compression
+        .or(config.snapshot_compression)
+        .unwrap_or_default();
+
+    let base_path = options
+        .data_dir
+        .or(config.data_dir)
+        .unwrap_or_else(|| PathBuf::from(format!("data-{}", id)));
 
-    let tls_config = if options.key_path.is_some() && options.cert_path.is_some() {
+    let key_path = options.key_path.or(config.tls.key_path);
+    let cert_path = options.cert_path.or(config.tls.cert_path);
+    let tls_config = if let (Some(key_path), Some(cert_path)) = (key_path, cert_path) {
         Some(RSQliteNodeTlsConfig {
-            key_path: options.key_path.unwrap(),
-            cert_path: options.cert_path.unwrap(),
-            accept_invalid_certificates: options.accept_invalid_certificates.unwrap_or(false),
+            key_path,
+            cert_path,
+            accept_invalid_certificates: options
+                .accept_invalid_certificates
+                .or(config.tls.accept_invalid_certificates)
+                .unwrap_or(false),
         })
     } else {
         None
@@ -97,13 +346,17 @@ async fn main() -> anyhow::Result<()> {
 
     if base_path.is_dir() {
         start_example_raft_node(
-            options.id,
+            id,
             base_path,
-            options.http_addr,
-            options.rpc_addr,
-            options.notifications_addr,
+            http_addr,
+            rpc_addr,
+            notifications_addr,
             tls_config,
-            options.no_database_encryption.unwrap_or(false),
+            read_mode,
+            forward_reads,
+            snapshot_compression,
+            raft_timing,
+            compaction_policy,
         )
         .await?;
         Ok(())
@@ -154,15 +407,24 @@ async fn main() -> anyhow::Result<()> {
             members.push((node_id, http_addr, rpc_addr));
         }
         init_example_raft_node(
-            options.id,
+            id,
             base_path,
             leader,
-            options.http_addr,
-            options.rpc_addr,
-            options.notifications_addr,
+            http_addr,
+            rpc_addr,
+            notifications_addr,
             members,
             tls_config,
-            options.no_database_encryption.unwrap_or(false),
+            options.write_mode.or(config.write_mode).unwrap_or_default(),
+            read_mode,
+            forward_reads,
+            snapshot_compression,
+            raft_timing,
+            compaction_policy,
+            options
+                .no_database_encryption
+                .or(config.no_database_encryption)
+                .unwrap_or(false),
         )
         .await
     }
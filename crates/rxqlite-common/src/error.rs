@@ -0,0 +1,164 @@
+//! The errors nodes answer in [`MessageResponse::Error`](crate::MessageResponse::Error), and
+//! the ones clients return.
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::DeError;
+
+// the primary result codes of sqlite, the low byte of its extended result codes
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_ERROR: i32 = 1;
+const SQLITE_CONSTRAINT: i32 = 19;
+
+// the extended result codes of the constraints
+const SQLITE_CONSTRAINT_CHECK: i32 = 275;
+const SQLITE_CONSTRAINT_FOREIGNKEY: i32 = 787;
+const SQLITE_CONSTRAINT_NOTNULL: i32 = 1299;
+const SQLITE_CONSTRAINT_PRIMARYKEY: i32 = 1555;
+const SQLITE_CONSTRAINT_UNIQUE: i32 = 2067;
+
+/// The constraint a write violated.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintKind {
+    Unique,
+    PrimaryKey,
+    NotNull,
+    Check,
+    ForeignKey,
+    /// Any other constraint, such as one raised by a trigger.
+    Other,
+}
+
+/// What went wrong.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A constraint of the schema rejected a write.
+    Constraint(ConstraintKind),
+    /// The database was busy or a table was locked.
+    Busy,
+    /// Sqlite rejected the sql with its generic error: a syntax error, a table or a column
+    /// that doesn't exist, a table that already exists...
+    Sql,
+    /// The request was rejected before reaching the database, such as a write calling
+    /// non-deterministic functions or parameters that don't match the statement.
+    InvalidRequest,
+    /// The node hasn't caught up with the leader as far as a read asked for.
+    NotCaughtUp,
+    /// No leader could be found to send the request to.
+    NotLeader,
+    /// The node couldn't be reached, or its response was lost.
+    Network,
+    /// A read matched no row where one was expected.
+    RowNotFound,
+    /// Rows couldn't be decoded into the type asked for.
+    Decode,
+    /// Any other error.
+    Other,
+}
+
+/// An error of a request, as answered by a node or returned by a client.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RXQLiteError {
+    pub kind: ErrorKind,
+    /// The extended result code of sqlite, for the errors sqlite reported.
+    pub code: Option<i32>,
+    pub message: String,
+}
+
+impl RXQLiteError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidRequest, message)
+    }
+
+    pub fn other(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Other, message)
+    }
+
+    /// An error sqlite reported with the extended result `code`.
+    pub fn sqlite(code: i32, message: impl Into<String>) -> Self {
+        let kind = match code & 0xff {
+            SQLITE_CONSTRAINT => ErrorKind::Constraint(match code {
+                SQLITE_CONSTRAINT_UNIQUE => ConstraintKind::Unique,
+                SQLITE_CONSTRAINT_PRIMARYKEY => ConstraintKind::PrimaryKey,
+                SQLITE_CONSTRAINT_NOTNULL => ConstraintKind::NotNull,
+                SQLITE_CONSTRAINT_CHECK => ConstraintKind::Check,
+                SQLITE_CONSTRAINT_FOREIGNKEY => ConstraintKind::ForeignKey,
+                _ => ConstraintKind::Other,
+            }),
+            SQLITE_BUSY | SQLITE_LOCKED => ErrorKind::Busy,
+            SQLITE_ERROR => ErrorKind::Sql,
+            _ => ErrorKind::Other,
+        };
+        Self {
+            kind,
+            code: Some(code),
+            message: message.into(),
+        }
+    }
+
+    /// Prefixes the message of the error, keeping its kind and code.
+    pub fn context(mut self, context: impl std::fmt::Display) -> Self {
+        self.message = format!("{}: {}", context, self.message);
+        self
+    }
+
+    /// Whether sending the request again may succeed. A write that failed with
+    /// [`ErrorKind::Network`] may have been applied, see [`RequestId`](crate::RequestId).
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Busy | ErrorKind::NotCaughtUp | ErrorKind::NotLeader | ErrorKind::Network
+        )
+    }
+}
+
+impl std::fmt::Display for RXQLiteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RXQLiteError {}
+
+impl From<DeError> for RXQLiteError {
+    fn from(err: DeError) -> Self {
+        Self::new(ErrorKind::Decode, err.to_string())
+    }
+}
+
+// log entries written before errors had a kind hold their message only
+impl<'de> Deserialize<'de> for RXQLiteError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Message(String),
+            Error {
+                kind: ErrorKind,
+                code: Option<i32>,
+                message: String,
+            },
+        }
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Message(message) => Self::other(message),
+            Stored::Error {
+                kind,
+                code,
+                message,
+            } => Self {
+                kind,
+                code,
+                message,
+            },
+        })
+    }
+}
@@ -1,8 +1,17 @@
 #![deny(unused_extern_crates)]
 #![deny(warnings)]
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+mod de;
+mod error;
+pub use de::{from_row, from_rows, DeError};
+pub use error::{ConstraintKind, ErrorKind, RXQLiteError};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Value {
@@ -53,6 +62,21 @@ impl From<DateTime<Utc>> for Value {
     }
 }
 
+impl From<Vec<u8>> for Value {
+    fn from(blob: Vec<u8>) -> Self {
+        Self::Blob(blob)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Self::Null,
+        }
+    }
+}
+
 pub trait FromValueRef {
     fn from_value_ref(value: &Value) -> Self;
 }
@@ -184,11 +208,332 @@ impl FromValueRef for DateTime<Utc> {
     }
 }
 
+/// Error returned when a [`Value`] can't be converted to the requested type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    /// The value is NULL and the requested type isn't an `Option`.
+    UnexpectedNull,
+    /// The value has a type that can't represent the requested type.
+    Mismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// The value has the right type but doesn't fit in the requested type.
+    OutOfRange { expected: &'static str, value: String },
+    /// The value couldn't be parsed as the requested type.
+    Parse {
+        expected: &'static str,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for ValueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedNull => write!(f, "unexpected NULL value"),
+            Self::Mismatch { expected, found } => {
+                write!(f, "can't convert a {} value to {}", found, expected)
+            }
+            Self::OutOfRange { expected, value } => {
+                write!(f, "{} is out of the range of {}", value, expected)
+            }
+            Self::Parse { expected, message } => {
+                write!(f, "invalid {} value: {}", expected, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl Value {
+    /// The name of the variant, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "Null",
+            Self::Bool(_) => "Bool",
+            Self::Int(_) => "Int",
+            Self::F32(_) => "F32",
+            Self::F64(_) => "F64",
+            Self::String(_) => "String",
+            Self::DateTime(_) => "DateTime",
+            Self::Blob(_) => "Blob",
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+    pub fn try_get<T: TryFromValue>(&self) -> Result<T, ValueError> {
+        T::try_from_value(self)
+    }
+}
+
+/// Strict conversion from a [`Value`]: unlike [`FromValueRef`], a value that
+/// doesn't represent the requested type is reported as an error instead of
+/// being coerced.
+pub trait TryFromValue: Sized {
+    fn try_from_value(value: &Value) -> Result<Self, ValueError>;
+}
+
+fn mismatch<T>(expected: &'static str, value: &Value) -> Result<T, ValueError> {
+    match value {
+        Value::Null => Err(ValueError::UnexpectedNull),
+        value => Err(ValueError::Mismatch {
+            expected,
+            found: value.type_name(),
+        }),
+    }
+}
+
+impl<T: TryFromValue> TryFromValue for Option<T> {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::Null => Ok(None),
+            v => T::try_from_value(v).map(Some),
+        }
+    }
+}
+
+impl TryFromValue for i64 {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::Int(i) => Ok(*i),
+            v => mismatch("i64", v),
+        }
+    }
+}
+
+macro_rules! try_from_int_value {
+    ($($ty:ty),*) => {
+        $(
+            impl TryFromValue for $ty {
+                fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+                    let i = match v {
+                        Value::Int(i) => *i,
+                        v => return mismatch(stringify!($ty), v),
+                    };
+                    <$ty>::try_from(i).map_err(|_| ValueError::OutOfRange {
+                        expected: stringify!($ty),
+                        value: i.to_string(),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+try_from_int_value!(i32, i16, u64);
+
+impl TryFromValue for bool {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::Bool(b) => Ok(*b),
+            // sqlite has no boolean storage class
+            Value::Int(0) => Ok(false),
+            Value::Int(1) => Ok(true),
+            Value::Int(i) => Err(ValueError::OutOfRange {
+                expected: "bool",
+                value: i.to_string(),
+            }),
+            v => mismatch("bool", v),
+        }
+    }
+}
+
+impl TryFromValue for f64 {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::F64(f) => Ok(*f),
+            Value::F32(f) => Ok(*f as _),
+            // sqlite stores a REAL without a fractional part as an INTEGER in NUMERIC columns,
+            // only the integers a f64 holds exactly are converted
+            // (compared as i128 since the cast back to i64 saturates at i64::MAX)
+            Value::Int(i) if (*i as f64) as i128 == *i as i128 => Ok(*i as _),
+            Value::Int(i) => Err(ValueError::OutOfRange {
+                expected: "f64",
+                value: i.to_string(),
+            }),
+            v => mismatch("f64", v),
+        }
+    }
+}
+
+impl TryFromValue for f32 {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::F32(f) => Ok(*f),
+            // sqlite stores every REAL as a double: only the ones a f32 holds are converted
+            Value::F64(f) if (*f as f32) as f64 == *f || f.is_nan() => Ok(*f as _),
+            Value::F64(f) => Err(ValueError::OutOfRange {
+                expected: "f32",
+                value: f.to_string(),
+            }),
+            Value::Int(i) if (*i as f32) as i128 == *i as i128 => Ok(*i as _),
+            Value::Int(i) => Err(ValueError::OutOfRange {
+                expected: "f32",
+                value: i.to_string(),
+            }),
+            v => mismatch("f32", v),
+        }
+    }
+}
+
+impl TryFromValue for String {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::String(s) => Ok(s.clone()),
+            v => mismatch("String", v),
+        }
+    }
+}
+
+impl TryFromValue for Vec<u8> {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::Blob(b) => Ok(b.clone()),
+            v => mismatch("Vec<u8>", v),
+        }
+    }
+}
+
+// the text formats sqlite's date and time functions produce
+const SQLITE_DATETIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"];
+
+fn parse_naive_datetime(s: &str) -> Result<NaiveDateTime, ValueError> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.naive_utc());
+    }
+    for format in SQLITE_DATETIME_FORMATS {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(dt);
+        }
+    }
+    Err(ValueError::Parse {
+        expected: "datetime",
+        message: s.to_string(),
+    })
+}
+
+impl TryFromValue for DateTime<Utc> {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::DateTime(dt) => Ok(*dt),
+            Value::String(s) => parse_naive_datetime(s).map(|dt| dt.and_utc()),
+            v => mismatch("DateTime<Utc>", v),
+        }
+    }
+}
+
+impl TryFromValue for NaiveDateTime {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::DateTime(dt) => Ok(dt.naive_utc()),
+            Value::String(s) => parse_naive_datetime(s),
+            v => mismatch("NaiveDateTime", v),
+        }
+    }
+}
+
+impl TryFromValue for uuid::Uuid {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        let parsed = match v {
+            // the representation sqlx uses
+            Value::Blob(b) => uuid::Uuid::from_slice(b),
+            Value::String(s) => uuid::Uuid::parse_str(s),
+            v => return mismatch("Uuid", v),
+        };
+        parsed.map_err(|err| ValueError::Parse {
+            expected: "Uuid",
+            message: err.to_string(),
+        })
+    }
+}
+
+impl TryFromValue for serde_json::Value {
+    fn try_from_value(v: &Value) -> Result<Self, ValueError> {
+        match v {
+            Value::String(s) => serde_json::from_str(s).map_err(|err| ValueError::Parse {
+                expected: "json",
+                message: err.to_string(),
+            }),
+            v => mismatch("serde_json::Value", v),
+        }
+    }
+}
+
 pub type Col = Value;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// Description of a result column.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// The sqlite type of the column as declared in the table definition,
+    /// `None` for expressions whose type can't be inferred.
+    pub decl_type: Option<String>,
+    /// Whether the column may contain NULL, only told when the query matched no row,
+    /// `None` otherwise or when sqlite can't tell.
+    pub nullable: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum RowError {
+    ColumnNotFound(String),
+    ColumnIndexOutOfBounds { index: usize, len: usize },
+    Decode { index: usize, source: ValueError },
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ColumnNotFound(name) => write!(f, "no column named {:?}", name),
+            Self::ColumnIndexOutOfBounds { index, len } => {
+                write!(f, "column index {} out of bounds (row has {} columns)", index, len)
+            }
+            Self::Decode { index, source } => write!(f, "column {}: {}", index, source),
+        }
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A type that can be used to index a [`Row`]: a column position or a column name.
+pub trait ColumnIndex {
+    fn index(&self, row: &Row) -> Result<usize, RowError>;
+}
+
+impl ColumnIndex for usize {
+    fn index(&self, row: &Row) -> Result<usize, RowError> {
+        if *self < row.len() {
+            Ok(*self)
+        } else {
+            Err(RowError::ColumnIndexOutOfBounds {
+                index: *self,
+                len: row.len(),
+            })
+        }
+    }
+}
+
+impl ColumnIndex for &str {
+    fn index(&self, row: &Row) -> Result<usize, RowError> {
+        row.column_index(self)
+            .ok_or_else(|| RowError::ColumnNotFound(self.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Row {
     pub inner: Vec<Col>,
+    // shared with the other rows of the same result, restored by `Rows` on deserialization
+    #[serde(skip)]
+    columns: Arc<Vec<Column>>,
 }
 
 impl std::ops::Deref for Row {
@@ -208,40 +553,394 @@ impl Row {
     pub fn get<T: FromValueRef>(&self, idx: usize) -> T {
         T::from_value_ref(&self[idx])
     }
+    /// Panics if there is no column named `name`.
+    pub fn get_by_name<T: FromValueRef>(&self, name: &str) -> T {
+        match self.column_index(name) {
+            Some(idx) => self.get(idx),
+            None => panic!("no column named {:?}", name),
+        }
+    }
+    /// Unlike [`Row::get`], reports a missing column or a value that doesn't convert to `T`.
+    pub fn try_get<T: TryFromValue, I: ColumnIndex>(&self, index: I) -> Result<T, RowError> {
+        let idx = index.index(self)?;
+        T::try_from_value(&self[idx]).map_err(|source| RowError::Decode { index: idx, source })
+    }
+    /// The columns of the result this row belongs to,
+    /// empty for a row that wasn't returned by a query.
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
 }
 impl From<Vec<Value>> for Row {
     fn from(inner: Vec<Value>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            columns: Default::default(),
+        }
+    }
+}
+
+/// The rows returned by a query along with the description of their columns.
+#[derive(Debug, Default, Clone)]
+pub struct Rows {
+    columns: Arc<Vec<Column>>,
+    rows: Vec<Row>,
+    executed: Option<ExecuteResult>,
+}
+
+impl Rows {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns: Arc::new(columns),
+            rows: vec![],
+            executed: None,
+        }
+    }
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+    /// What the query changed when it wrote to the database, such as an
+    /// `INSERT ... RETURNING`, `None` when it changed no row.
+    pub fn executed(&self) -> Option<ExecuteResult> {
+        self.executed
+    }
+    pub fn set_executed(&mut self, executed: Option<ExecuteResult>) {
+        self.executed = executed;
+    }
+    pub fn add_row(&mut self, values: Vec<Value>) {
+        self.rows.push(Row {
+            inner: values,
+            columns: self.columns.clone(),
+        });
+    }
+}
+
+impl std::ops::Deref for Rows {
+    type Target = Vec<Row>;
+    fn deref(&self) -> &Self::Target {
+        &self.rows
+    }
+}
+
+impl std::ops::DerefMut for Rows {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.rows
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = Row;
+    type IntoIter = std::vec::IntoIter<Row>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = &'a Row;
+    type IntoIter = std::slice::Iter<'a, Row>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+// the column header is sent once per result rather than once per row
+#[derive(Serialize)]
+struct RowsRef<'a> {
+    columns: &'a [Column],
+    rows: &'a [Row],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    executed: Option<ExecuteResult>,
+}
+
+#[derive(Deserialize)]
+struct RawRows {
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+    #[serde(default)]
+    executed: Option<ExecuteResult>,
+}
+
+impl Serialize for Rows {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RowsRef {
+            columns: &self.columns,
+            rows: &self.rows,
+            executed: self.executed,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rows {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawRows::deserialize(deserializer)?;
+        let mut rows = Rows::new(raw.columns);
+        for row in raw.rows {
+            rows.add_row(row.inner);
+        }
+        rows.set_executed(raw.executed);
+        Ok(rows)
+    }
+}
+
+/// The parameters of a statement. Untagged, positional parameters being serialized as the
+/// list of values messages held before named parameters.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Parameters {
+    /// Bound to `?`, `?NNN` and `$NNN` placeholders.
+    Positional(Vec<Value>),
+    /// Bound to `:name`, `@name` and `$name` placeholders. A key may be given with
+    /// its prefix to match only that placeholder, or without it to match any of them.
+    Named(BTreeMap<String, Value>),
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self::Positional(vec![])
+    }
+}
+
+impl Parameters {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Positional(values) => values.len(),
+            Self::Named(values) => values.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<Vec<Value>> for Parameters {
+    fn from(values: Vec<Value>) -> Self {
+        Self::Positional(values)
     }
 }
 
-pub type Rows = Vec<Row>;
+impl From<BTreeMap<String, Value>> for Parameters {
+    fn from(values: BTreeMap<String, Value>) -> Self {
+        Self::Named(values)
+    }
+}
+
+impl From<HashMap<String, Value>> for Parameters {
+    fn from(values: HashMap<String, Value>) -> Self {
+        Self::Named(values.into_iter().collect())
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    Execute(String, Vec<Value>),
-    Fetch(String, Vec<Value>),
-    FetchOne(String, Vec<Value>),
-    FetchOptional(String, Vec<Value>),
+    Execute(String, Parameters),
+    Fetch(String, Parameters),
+    FetchOne(String, Parameters),
+    FetchOptional(String, Parameters),
+    /// A list of statements applied atomically inside a single sqlite transaction:
+    /// if any statement fails, none of them is applied.
+    Transaction(Vec<Message>),
+    /// A list of statements executed in one round-trip and replicated as one raft entry.
+    /// Unlike a transaction, a failing statement doesn't prevent the others from being applied.
+    Batch(Vec<(String, Parameters)>),
+    /// The changes a write made to the database of the leader, replicated in place of its
+    /// statements when the cluster runs in changeset write mode. Nodes apply the changes
+    /// and answer `response`, the leader's response to the write.
+    ///
+    /// Only the nodes create changesets: the sql api rejects them.
+    Changeset {
+        changeset: Vec<u8>,
+        response: Box<MessageResponse>,
+    },
+    /// A write a client identified with [`RequestOptions::request_id`]. Nodes record its
+    /// response, and answer it instead of applying the write again when the client sends
+    /// it again with the same id.
+    ///
+    /// Only the nodes create them: the sql api rejects them.
+    WithRequestId {
+        request_id: RequestId,
+        message: Box<Message>,
+    },
 }
 
 impl Message {
-    pub fn sql(&self) -> &str {
+    /// The sql text of a single statement message, `None` for a transaction, a batch, a
+    /// changeset or an identified write.
+    pub fn sql(&self) -> Option<&str> {
         match self {
-            Self::Execute(s, _) => s.as_str(),
-            Self::Fetch(s, _) => s.as_str(),
-            Self::FetchOne(s, _) => s.as_str(),
-            Self::FetchOptional(s, _) => s.as_str(),
+            Self::Execute(s, _) => Some(s.as_str()),
+            Self::Fetch(s, _) => Some(s.as_str()),
+            Self::FetchOne(s, _) => Some(s.as_str()),
+            Self::FetchOptional(s, _) => Some(s.as_str()),
+            Self::Transaction(_) => None,
+            Self::Batch(_) => None,
+            Self::Changeset { .. } => None,
+            Self::WithRequestId { .. } => None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Options of a single request to the sql api, sent in the query string of its url.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RequestOptions {
+    /// Let the statements of a write call non-deterministic functions such as
+    /// `random()` or `datetime('now')`. Every node runs the statements again, so the
+    /// databases of the nodes may then differ.
+    #[serde(default)]
+    pub allow_non_deterministic: bool,
+    /// For a read: the index of a log entry the node must have applied before answering,
+    /// such as the `log_id.index` of the response to a previous write. The node waits for
+    /// it, or answers an error.
+    #[serde(default)]
+    pub min_applied_index: Option<u64>,
+    /// For a read: how old, in milliseconds, the data the node answers from may be.
+    /// The node waits until it has applied what the leader had committed at most this long
+    /// ago, or answers an error.
+    #[serde(default)]
+    pub max_staleness_ms: Option<u64>,
+    /// For a write: the client id of its [`RequestId`], set with
+    /// [`RequestOptions::request_id`].
+    #[serde(default)]
+    pub client_id: Option<u64>,
+    /// For a write: the sequence number of its [`RequestId`].
+    #[serde(default)]
+    pub sequence: Option<u64>,
+}
+
+impl RequestOptions {
+    pub fn allow_non_deterministic(mut self, allow_non_deterministic: bool) -> Self {
+        self.allow_non_deterministic = allow_non_deterministic;
+        self
+    }
+
+    pub fn min_applied_index(mut self, min_applied_index: u64) -> Self {
+        self.min_applied_index = Some(min_applied_index);
+        self
+    }
+
+    pub fn max_staleness(mut self, max_staleness: std::time::Duration) -> Self {
+        self.max_staleness_ms = Some(max_staleness.as_millis() as u64);
+        self
+    }
+
+    /// Identifies a write, so that sending it again, after a timeout for instance, doesn't
+    /// apply it twice: a node that already applied a write with this id answers the
+    /// response it recorded then.
+    pub fn request_id(mut self, request_id: RequestId) -> Self {
+        self.client_id = Some(request_id.client_id);
+        self.sequence = Some(request_id.sequence);
+        self
+    }
+
+    /// The id of the request, when both its client id and sequence number are set.
+    pub fn id(&self) -> Option<RequestId> {
+        match (self.client_id, self.sequence) {
+            (Some(client_id), Some(sequence)) => Some(RequestId {
+                client_id,
+                sequence,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The query string, without the leading `?`, empty for the default options.
+    pub fn query_string(&self) -> String {
+        let mut pairs = vec![];
+        if self.allow_non_deterministic {
+            pairs.push("allow_non_deterministic=true".to_string());
+        }
+        if let Some(min_applied_index) = self.min_applied_index {
+            pairs.push(format!("min_applied_index={}", min_applied_index));
+        }
+        if let Some(max_staleness_ms) = self.max_staleness_ms {
+            pairs.push(format!("max_staleness_ms={}", max_staleness_ms));
+        }
+        if let Some(client_id) = self.client_id {
+            pairs.push(format!("client_id={}", client_id));
+        }
+        if let Some(sequence) = self.sequence {
+            pairs.push(format!("sequence={}", sequence));
+        }
+        pairs.join("&")
+    }
+}
+
+/// Identifies a write among the ones of every client: `client_id` tells the clients
+/// apart, and each client numbers its writes with `sequence`.
+///
+/// Nodes only remember the responses of the last writes: a write sent again long after
+/// the first time may be applied again.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    pub client_id: u64,
+    pub sequence: u64,
+}
+
+/// Hands out the [`RequestId`]s of a client: the client id is picked at random, and the
+/// sequence number grows with each id.
+#[derive(Debug)]
+pub struct RequestIds {
+    client_id: u64,
+    next_sequence: AtomicU64,
+}
+
+impl RequestIds {
+    pub fn new() -> Self {
+        // hashers are keyed at random
+        let client_id = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        Self::with_client_id(client_id)
+    }
+
+    pub fn with_client_id(client_id: u64) -> Self {
+        Self {
+            client_id,
+            next_sequence: AtomicU64::new(0),
+        }
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    pub fn next_id(&self) -> RequestId {
+        RequestId {
+            client_id: self.client_id,
+            sequence: self.next_sequence.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The outcome of a statement that doesn't return rows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecuteResult {
+    pub rows_affected: u64,
+    pub last_insert_rowid: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageResponse {
     Rows(Rows),
-    Error(String),
+    Error(RXQLiteError),
+    /// The responses of a committed transaction, one per statement.
+    Transaction(Vec<MessageResponse>),
+    Executed(ExecuteResult),
+    /// The responses of a batch, one per statement: either `Executed` or `Error`.
+    Batch(Vec<MessageResponse>),
 }
 
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RSQliteNodeTlsConfig {
     pub key_path: String,
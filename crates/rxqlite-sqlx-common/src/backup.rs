@@ -0,0 +1,98 @@
+//! Copies of a database taken with the online backup api of sqlite while the other
+//! connections keep reading it and writing to it.
+
+use std::ffi::c_int;
+use std::time::Duration;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_backup, sqlite3_backup_finish, sqlite3_backup_init, sqlite3_backup_step,
+    SQLITE_BUSY, SQLITE_DONE, SQLITE_LOCKED, SQLITE_OK,
+};
+use rxqlite_common::RXQLiteError;
+use sqlx::{Acquire, Database, Transaction};
+
+use crate::changeset::error_message;
+use crate::{sql_error, SqlxDb};
+
+type SqlxConnection = <SqlxDb as Database>::Connection;
+
+/// How many pages a step of a backup copies, other tasks running between two steps.
+const PAGES_PER_STEP: c_int = 1024;
+
+/// How long to wait before another step when a database is locked.
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Begins a transaction which reads the database as it is now until it ends, whatever the
+/// other connections write meanwhile.
+pub async fn begin_read<'c, A>(conn: A) -> Result<Transaction<'c, SqlxDb>, RXQLiteError>
+where
+    A: Acquire<'c, Database = SqlxDb>,
+{
+    let mut transaction = conn.begin().await.map_err(sql_error)?;
+    // a deferred transaction only starts reading with its first statement
+    sqlx::query("SELECT COUNT(*) FROM sqlite_master")
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(sql_error)?;
+    Ok(transaction)
+}
+
+/// Copies the main database of `source` over the one of `destination`, which must be
+/// encrypted with the same key, if any.
+///
+/// The copy is the database as it was when the backup started, or when `source` began the
+/// transaction it is in: `source` holds a read transaction until the copy is done, which
+/// doesn't block the other connections of a database in WAL mode, and keeps sqlite from
+/// starting the copy over when they write.
+pub async fn backup_database(
+    source: &mut SqlxConnection,
+    destination: &mut SqlxConnection,
+) -> Result<(), RXQLiteError> {
+    let mut transaction = begin_read(source).await?;
+    let mut source_handle = transaction.lock_handle().await.map_err(sql_error)?;
+    let mut destination_handle = destination.lock_handle().await.map_err(sql_error)?;
+    let backup = {
+        let source = source_handle.as_raw_handle().as_ptr();
+        let destination = destination_handle.as_raw_handle().as_ptr();
+        // SAFETY: both connections are locked until the backup is finished.
+        let backup =
+            unsafe { sqlite3_backup_init(destination, c"main".as_ptr(), source, c"main".as_ptr()) };
+        if backup.is_null() {
+            // SAFETY: the connection is still locked
+            return Err(RXQLiteError::other(unsafe { error_message(destination) }));
+        }
+        Backup {
+            backup,
+            destination,
+        }
+    };
+    loop {
+        // SAFETY: the connections are still locked
+        match unsafe { sqlite3_backup_step(backup.backup, PAGES_PER_STEP) } {
+            SQLITE_DONE => break,
+            SQLITE_OK => sqlx_core::rt::yield_now().await,
+            SQLITE_BUSY | SQLITE_LOCKED => sqlx_core::rt::sleep(BUSY_RETRY_DELAY).await,
+            _ => break,
+        }
+    }
+    // SAFETY: the connections are still locked, and the backup isn't used after this
+    let result = unsafe {
+        match sqlite3_backup_finish(backup.backup) {
+            SQLITE_OK => Ok(()),
+            rc => Err(RXQLiteError::sqlite(rc, error_message(backup.destination))),
+        }
+    };
+    drop(destination_handle);
+    drop(source_handle);
+    transaction.rollback().await.map_err(sql_error)?;
+    result
+}
+
+/// A backup, and the connection it copies to.
+struct Backup {
+    backup: *mut sqlite3_backup,
+    destination: *mut sqlite3,
+}
+
+// SAFETY: the backup is only used while the connections it copies between are locked.
+unsafe impl Send for Backup {}
@@ -0,0 +1,299 @@
+//! Changeset write mode: the leader runs a write against its own database while the
+//! session extension records the rows it changes, rolls it back, and replicates the
+//! recorded changes instead of the statements. Every node, the leader included, then
+//! applies the changes with `sqlite3changeset_apply`, so the outcome of the write doesn't
+//! depend on what its statements would compute on each node.
+//!
+//! The session extension doesn't record schema changes, nor the rows of a table without
+//! a declared primary key. Writes that make such changes, and the ones with statements
+//! that would end the transaction the changes are recorded in, are replicated as
+//! statements.
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_changeset_iter, sqlite3_errmsg, sqlite3_free, sqlite3_session,
+    sqlite3changeset_apply, sqlite3session_attach, sqlite3session_changeset, sqlite3session_create,
+    sqlite3session_delete, sqlite3session_table_filter, SQLITE_ABORT, SQLITE_CHANGESET_ABORT,
+    SQLITE_OK,
+};
+use rxqlite_common::{Message, MessageResponse, RXQLiteError};
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use sqlx::{Connection, Database, Pool};
+
+use crate::{sql_error, SqlxDb};
+
+type SqlxConnection = <SqlxDb as Database>::Connection;
+
+/// Statements that end the transaction the changes are recorded in, or can't run inside
+/// one.
+const TRANSACTION_STATEMENTS: &[&str] = &[
+    "begin",
+    "commit",
+    "end",
+    "rollback",
+    "savepoint",
+    "release",
+    "attach",
+    "detach",
+    "vacuum",
+];
+
+/// What to replicate for a write.
+#[derive(Debug)]
+pub enum Capture {
+    /// A [`Message::Changeset`] holding the changes of the write and its response.
+    Changeset(Message),
+    /// The changes of the write can't be recorded: its statements must be replicated.
+    Statements,
+    /// The write failed without changing anything, there is nothing to replicate.
+    Failed(MessageResponse),
+}
+
+/// Runs `message` against the database of `pool` and records the changes it makes,
+/// leaving the database as it was.
+///
+/// The database must not change between the capture and the application of the
+/// changeset: the caller serializes its writes and replicates each one before capturing
+/// the next.
+pub async fn capture_changeset(pool: &Pool<SqlxDb>, message: &Message) -> Capture {
+    if !is_capturable(message) {
+        return Capture::Statements;
+    }
+    let pool = pool.clone();
+    let message = message.clone();
+    // the session must be deleted even if the caller goes away
+    sqlx_core::rt::spawn(async move {
+        let mut conn = match pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => return Capture::Failed(MessageResponse::Error(sql_error(err))),
+        };
+        match capture(&mut conn, message).await {
+            Ok(capture) => capture,
+            Err(err) => Capture::Failed(MessageResponse::Error(RXQLiteError::other(err))),
+        }
+    })
+    .await
+}
+
+async fn capture(conn: &mut SqlxConnection, message: Message) -> Result<Capture, String> {
+    let mut transaction = conn.begin().await.map_err(|err| err.to_string())?;
+    let schema_before = schema_version(&mut transaction).await?;
+    let session = Session::attach(&mut transaction).await?;
+    let response = crate::do_message(&mut transaction, message).await;
+    let (changeset, tables) = session.finish(&mut transaction).await?;
+    let changed_schema = schema_before != schema_version(&mut transaction).await?;
+    let mut untracked_table = false;
+    for table in tables {
+        if !has_primary_key(&mut transaction, &table).await? {
+            untracked_table = true;
+            break;
+        }
+    }
+    transaction
+        .rollback()
+        .await
+        .map_err(|err| err.to_string())?;
+    if changed_schema || untracked_table {
+        return Ok(Capture::Statements);
+    }
+    if changeset.is_empty() {
+        if let MessageResponse::Error(_) = response {
+            return Ok(Capture::Failed(response));
+        }
+    }
+    Ok(Capture::Changeset(Message::Changeset {
+        changeset,
+        response: Box::new(response),
+    }))
+}
+
+async fn schema_version(conn: &mut SqlxConnection) -> Result<i64, String> {
+    sqlx::query_scalar("PRAGMA schema_version")
+        .fetch_one(conn)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn has_primary_key(conn: &mut SqlxConnection, table: &str) -> Result<bool, String> {
+    let key_columns: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?1) WHERE pk > 0")
+            .bind(table)
+            .fetch_one(conn)
+            .await
+            .map_err(|err| err.to_string())?;
+    Ok(key_columns > 0)
+}
+
+fn is_capturable(message: &Message) -> bool {
+    match message {
+        Message::Changeset { .. } | Message::WithRequestId { .. } => false,
+        message => runs_in_transaction(message),
+    }
+}
+
+/// Whether `message` can run inside a transaction, none of its statements ending it.
+pub(crate) fn runs_in_transaction(message: &Message) -> bool {
+    match message {
+        Message::Transaction(messages) => messages.iter().all(runs_in_transaction),
+        Message::Batch(statements) => statements
+            .iter()
+            .all(|(sql, _)| sql_runs_in_transaction(sql)),
+        Message::Changeset { .. } => true,
+        Message::WithRequestId { message, .. } => runs_in_transaction(message),
+        message => match message.sql() {
+            Some(sql) => sql_runs_in_transaction(sql),
+            None => true,
+        },
+    }
+}
+
+fn sql_runs_in_transaction(sql: &str) -> bool {
+    let tokens = match Tokenizer::new(&SQLiteDialect {}, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+    let mut statement_start = true;
+    for token in tokens {
+        match token {
+            Token::Whitespace(_) => {}
+            Token::SemiColon => statement_start = true,
+            Token::Word(word) if statement_start && word.quote_style.is_none() => {
+                if TRANSACTION_STATEMENTS.contains(&word.value.to_ascii_lowercase().as_str()) {
+                    return false;
+                }
+                statement_start = false;
+            }
+            _ => statement_start = false,
+        }
+    }
+    true
+}
+
+/// Applies the changes of a [`Message::Changeset`]. A change that conflicts with the
+/// database aborts the whole changeset, the same way on every node.
+pub(crate) async fn apply_changeset(
+    conn: &mut SqlxConnection,
+    changeset: &[u8],
+) -> Result<(), RXQLiteError> {
+    let len =
+        c_int::try_from(changeset.len()).map_err(|_| RXQLiteError::other("changeset too large"))?;
+    let mut handle = conn.lock_handle().await.map_err(sql_error)?;
+    let db = handle.as_raw_handle().as_ptr();
+    // SAFETY: the connection is locked while sqlite applies the changes, and sqlite
+    // doesn't write to the changeset.
+    let rc = unsafe {
+        sqlite3changeset_apply(
+            db,
+            len,
+            changeset.as_ptr() as *mut c_void,
+            None,
+            Some(abort_on_conflict),
+            ptr::null_mut(),
+        )
+    };
+    match rc {
+        SQLITE_OK => Ok(()),
+        SQLITE_ABORT => Err(RXQLiteError::other(
+            "the changes of the write conflict with the database",
+        )),
+        // SAFETY: the connection is still locked
+        _ => Err(RXQLiteError::sqlite(rc, unsafe { error_message(db) })),
+    }
+}
+
+unsafe extern "C" fn abort_on_conflict(
+    _ctx: *mut c_void,
+    _conflict: c_int,
+    _iter: *mut sqlite3_changeset_iter,
+) -> c_int {
+    SQLITE_CHANGESET_ABORT
+}
+
+/// Called by sqlite the first time a statement changes a table, with the `Vec<String>`
+/// of the session as `ctx`.
+unsafe extern "C" fn record_table(ctx: *mut c_void, table: *const c_char) -> c_int {
+    let tables = &mut *(ctx as *mut Vec<String>);
+    tables.push(CStr::from_ptr(table).to_string_lossy().into_owned());
+    // record the changes of every table
+    1
+}
+
+pub(crate) unsafe fn error_message(db: *mut sqlite3) -> String {
+    CStr::from_ptr(sqlite3_errmsg(db))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A session recording the changes made to the main database of a connection.
+struct Session {
+    session: *mut sqlite3_session,
+    // the tables changed so far, filled in by `record_table` while statements run, boxed
+    // so that the address given to sqlite doesn't change when the session moves
+    #[allow(clippy::box_collection)]
+    tables: Box<Vec<String>>,
+}
+
+// SAFETY: the session is only used while the connection it is attached to is locked.
+unsafe impl Send for Session {}
+
+impl Session {
+    async fn attach(conn: &mut SqlxConnection) -> Result<Self, String> {
+        let mut handle = conn.lock_handle().await.map_err(|err| err.to_string())?;
+        let db = handle.as_raw_handle().as_ptr();
+        let mut session: *mut sqlite3_session = ptr::null_mut();
+        let mut tables: Box<Vec<String>> = Box::default();
+        // SAFETY: the connection is locked, and `tables` lives as long as the session.
+        unsafe {
+            if sqlite3session_create(db, c"main".as_ptr(), &mut session) != SQLITE_OK {
+                return Err(error_message(db));
+            }
+            sqlite3session_table_filter(
+                session,
+                Some(record_table),
+                &mut *tables as *mut Vec<String> as *mut c_void,
+            );
+            if sqlite3session_attach(session, ptr::null()) != SQLITE_OK {
+                sqlite3session_delete(session);
+                return Err(error_message(db));
+            }
+        }
+        Ok(Self { session, tables })
+    }
+
+    /// Deletes the session, returning the changes it recorded and the tables they were
+    /// made to.
+    async fn finish(mut self, conn: &mut SqlxConnection) -> Result<(Vec<u8>, Vec<String>), String> {
+        let mut handle = conn.lock_handle().await.map_err(|err| err.to_string())?;
+        let db = handle.as_raw_handle().as_ptr();
+        // SAFETY: the connection is locked, and sqlite allocated `len` bytes at `buffer`.
+        let changeset = unsafe {
+            let mut len: c_int = 0;
+            let mut buffer: *mut c_void = ptr::null_mut();
+            let rc = sqlite3session_changeset(self.session, &mut len, &mut buffer);
+            let changeset = if rc == SQLITE_OK && !buffer.is_null() {
+                Ok(std::slice::from_raw_parts(buffer as *const u8, len as usize).to_vec())
+            } else if rc == SQLITE_OK {
+                Ok(vec![])
+            } else {
+                Err(error_message(db))
+            };
+            sqlite3_free(buffer);
+            changeset
+        };
+        let tables = std::mem::take(&mut *self.tables);
+        // deletes the session while the connection is still locked
+        drop(self);
+        Ok((changeset?, tables))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // SAFETY: the session is dropped by the owner of the connection it is attached to,
+        // so nothing else runs on the connection meanwhile.
+        unsafe { sqlite3session_delete(self.session) }
+    }
+}
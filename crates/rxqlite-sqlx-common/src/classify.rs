@@ -0,0 +1,180 @@
+//! Tells the statements that must be replicated through raft from the ones a node
+//! can answer from its own database.
+//!
+//! SQLite is the authority on what a statement does: each statement is prepared
+//! against the local database and asked whether it is read only with
+//! `sqlite3_stmt_readonly`. When a statement can't be prepared, for instance because
+//! it uses a table created by a previous statement of the same string, the whole
+//! string is classified by parsing it with sqlparser instead.
+
+use std::ffi::c_char;
+use std::ptr;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_errmsg, sqlite3_finalize, sqlite3_prepare_v2, sqlite3_stmt,
+    sqlite3_stmt_readonly, SQLITE_OK,
+};
+use rxqlite_common::Message;
+use sqlparser::ast::{Query, SetExpr, Statement};
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::parser::Parser;
+use sqlx::Pool;
+
+use crate::SqlxDb;
+
+/// Transactions and batches are always replicated as a single raft entry,
+/// whatever their statements are.
+pub fn is_message_write(message: &Message) -> anyhow::Result<bool> {
+    match message.sql() {
+        Some(sql) => is_query_write(sql),
+        None => Ok(true),
+    }
+}
+
+/// Like [`is_message_write`], asking the database of `pool` what the statements do.
+pub async fn is_message_write_on(pool: &Pool<SqlxDb>, message: &Message) -> anyhow::Result<bool> {
+    match message.sql() {
+        Some(sql) => is_query_write_on(pool, sql).await,
+        None => Ok(true),
+    }
+}
+
+/// Classifies `sql`, which may hold several statements, as a whole: it is a write
+/// as soon as one of its statements is.
+pub async fn is_query_write_on(pool: &Pool<SqlxDb>, sql: &str) -> anyhow::Result<bool> {
+    let mut conn = pool.acquire().await?;
+    let mut handle = conn.lock_handle().await?;
+    match prepared_is_write(handle.as_raw_handle().as_ptr(), sql) {
+        Ok(is_write) => Ok(is_write),
+        Err(prepare_err) => match is_query_write(sql) {
+            Ok(is_write) => Ok(is_write),
+            // sqlite's message is the more accurate one
+            Err(_) => Err(anyhow::anyhow!(prepare_err)),
+        },
+    }
+}
+
+fn prepared_is_write(db: *mut sqlite3, sql: &str) -> Result<bool, String> {
+    let len = i32::try_from(sql.len()).map_err(|_| "statement too long".to_string())?;
+    let start = sql.as_ptr() as *const c_char;
+    let mut offset = 0usize;
+    while offset < sql.len() {
+        let mut stmt: *mut sqlite3_stmt = ptr::null_mut();
+        let mut tail: *const c_char = ptr::null();
+        // SAFETY: the connection is locked for the lifetime of `db`, and `offset` stays
+        // within `sql`, which sqlite reads up to `len - offset` bytes of.
+        let (rc, readonly) = unsafe {
+            let rc = sqlite3_prepare_v2(
+                db,
+                start.add(offset),
+                len - offset as i32,
+                &mut stmt,
+                &mut tail,
+            );
+            if rc != SQLITE_OK {
+                let message = std::ffi::CStr::from_ptr(sqlite3_errmsg(db))
+                    .to_string_lossy()
+                    .into_owned();
+                return Err(message);
+            }
+            let readonly = stmt.is_null() || sqlite3_stmt_readonly(stmt) != 0;
+            (sqlite3_finalize(stmt), readonly)
+        };
+        if rc != SQLITE_OK {
+            return Err(format!("failed to finalize statement: {}", rc));
+        }
+        let next = if tail.is_null() {
+            sql.len()
+        } else {
+            tail as usize - start as usize
+        };
+        if is_write_by_keyword(&sql[offset..next]).unwrap_or(!readonly) {
+            return Ok(true);
+        }
+        // nothing was consumed: only whitespace or comments were left
+        if next == offset {
+            break;
+        }
+        offset = next;
+    }
+    Ok(false)
+}
+
+/// Overrides `sqlite3_stmt_readonly` for the statements whose effect it doesn't
+/// account for:
+/// - transaction control, `ATTACH`, `DETACH` and `PRAGMA name = value` don't write to the
+///   database file but change the state of the connection, which every node must share,
+/// - `REINDEX` is reported read only when there's no index to rebuild,
+/// - `EXPLAIN` never runs the statement it explains.
+fn is_write_by_keyword(statement: &str) -> Option<bool> {
+    let keyword = first_keyword(statement).to_ascii_uppercase();
+    match keyword.as_str() {
+        "BEGIN" | "COMMIT" | "END" | "ROLLBACK" | "SAVEPOINT" | "RELEASE" | "ATTACH" | "DETACH"
+        | "REINDEX" => Some(true),
+        // `PRAGMA name(arg)` mostly queries a value
+        "PRAGMA" if statement.contains('=') => Some(true),
+        "EXPLAIN" => Some(false),
+        _ => None,
+    }
+}
+
+fn first_keyword(statement: &str) -> &str {
+    let mut rest = statement;
+    loop {
+        rest = rest.trim_start();
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map(|(_, rest)| rest).unwrap_or("");
+        } else {
+            break;
+        }
+    }
+    let end = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn is_for_update_or_share(query: &Query) -> bool {
+    !query.locks.is_empty()
+}
+
+fn is_set_expr_write(body: &SetExpr) -> bool {
+    match body {
+        SetExpr::Select(select) => select.into.is_some(),
+        SetExpr::Query(query) => is_read_query_write(query),
+        SetExpr::SetOperation { left, right, .. } => {
+            is_set_expr_write(left) || is_set_expr_write(right)
+        }
+        SetExpr::Values(_) | SetExpr::Table(_) => false,
+        SetExpr::Insert(_) | SetExpr::Update(_) => true,
+    }
+}
+
+fn is_read_query_write(query: &Query) -> bool {
+    is_for_update_or_share(query)
+        || is_set_expr_write(&query.body)
+        || query.with.as_ref().is_some_and(|with| {
+            with.cte_tables
+                .iter()
+                .any(|cte| is_read_query_write(&cte.query))
+        })
+}
+
+/// Classifies `sql` by parsing it, without a database at hand.
+pub fn is_query_write(sql: &str) -> anyhow::Result<bool> {
+    let ast = Parser::parse_sql(&SQLiteDialect {}, sql)?;
+    for stmt in ast {
+        let is_write = match stmt {
+            Statement::Query(query) => is_read_query_write(&query),
+            Statement::Explain { .. } | Statement::ExplainTable { .. } => false,
+            Statement::Pragma { is_eq, .. } => is_eq,
+            _ => true,
+        };
+        if is_write {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,399 @@
+//! Writes are replicated as sql text and run again by every node, so a statement
+//! whose result depends on the node that runs it, like `INSERT ... VALUES (random())`,
+//! leaves the nodes with different databases.
+//!
+//! SQLite tells what a statement calls: each statement is prepared against the local
+//! database, and the functions of the program it compiles to are checked. The program
+//! holds the calls of the triggers the statement fires, of the views it reads and of the
+//! column defaults it fills in, while a `CREATE TABLE` only stores its defaults. When a
+//! statement can't be prepared, for instance because it uses a table created by a
+//! previous statement of the same string, the whole string is scanned for calls instead.
+
+use std::collections::BTreeSet;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_column_text, sqlite3_errmsg, sqlite3_finalize, sqlite3_prepare_v2,
+    sqlite3_set_authorizer, sqlite3_step, sqlite3_stmt, SQLITE_DONE, SQLITE_FUNCTION,
+    SQLITE_INSERT, SQLITE_OK, SQLITE_ROW,
+};
+use rxqlite_common::Message;
+use sqlparser::dialect::SQLiteDialect;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use sqlx::{Database, Pool};
+
+use crate::SqlxDb;
+
+type SqlxConnection = <SqlxDb as Database>::Connection;
+
+/// Functions whose result differs from one node to the other.
+const NON_DETERMINISTIC_FUNCTIONS: &[&str] = &[
+    "random",
+    "randomblob",
+    "changes",
+    "total_changes",
+    "last_insert_rowid",
+];
+
+/// Date and time functions, non-deterministic when they read the clock: when their
+/// time value is `'now'` or is left out.
+const DATE_TIME_FUNCTIONS: &[&str] = &[
+    "date",
+    "time",
+    "datetime",
+    "julianday",
+    "unixepoch",
+    "strftime",
+    "timediff",
+];
+
+const CLOCK_KEYWORDS: &[&str] = &["current_date", "current_time", "current_timestamp"];
+
+/// Checks every statement of `message` by scanning its sql, without a database at hand.
+pub fn check_deterministic(message: &Message) -> Result<(), String> {
+    statements(message)
+        .into_iter()
+        .try_for_each(check_sql_deterministic)
+}
+
+/// Like [`check_deterministic`], asking the database of `pool` what the statements call.
+pub async fn check_deterministic_on(pool: &Pool<SqlxDb>, message: &Message) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(|err| err.to_string())?;
+    for sql in statements(message) {
+        check_sql_deterministic_on(&mut conn, sql).await?;
+    }
+    Ok(())
+}
+
+fn statements(message: &Message) -> Vec<&str> {
+    match message {
+        Message::Transaction(messages) => messages.iter().flat_map(statements).collect(),
+        Message::Batch(statements) => statements.iter().map(|(sql, _)| sql.as_str()).collect(),
+        message => message.sql().into_iter().collect(),
+    }
+}
+
+async fn check_sql_deterministic_on(conn: &mut SqlxConnection, sql: &str) -> Result<(), String> {
+    let compiled = {
+        let mut handle = conn.lock_handle().await.map_err(|err| err.to_string())?;
+        compile(handle.as_raw_handle().as_ptr(), sql)
+    };
+    let compiled = match compiled {
+        Ok(compiled) => compiled,
+        Err(_) => return check_sql_deterministic(sql),
+    };
+    for statement in compiled {
+        let mut clock_readers = None;
+        for function in &statement.functions {
+            let non_deterministic = if DATE_TIME_FUNCTIONS.contains(&function.as_str()) {
+                if clock_readers.is_none() {
+                    clock_readers = Some(clock_readers_of(conn, &statement).await?);
+                }
+                clock_readers
+                    .as_ref()
+                    .is_some_and(|readers| readers.contains(function))
+            } else {
+                NON_DETERMINISTIC_FUNCTIONS.contains(&function.as_str())
+                    || CLOCK_KEYWORDS.contains(&function.as_str())
+            };
+            if non_deterministic {
+                let is_call = !CLOCK_KEYWORDS.contains(&function.as_str());
+                return Err(non_deterministic_error(function, is_call));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What sqlite compiled for one statement.
+struct Compiled<'a> {
+    text: &'a str,
+    /// The functions the program calls.
+    functions: BTreeSet<String>,
+    /// The triggers and views the program calls functions from and the tables it inserts
+    /// rows into, whose definitions hold the arguments of the calls that are not in
+    /// `text`.
+    sources: BTreeSet<String>,
+}
+
+fn compile<'a>(db: *mut sqlite3, sql: &'a str) -> Result<Vec<Compiled<'a>>, String> {
+    let len = i32::try_from(sql.len()).map_err(|_| "statement too long".to_string())?;
+    let start = sql.as_ptr() as *const c_char;
+    let mut offset = 0usize;
+    let mut compiled = vec![];
+    while offset < sql.len() {
+        let mut stmt: *mut sqlite3_stmt = ptr::null_mut();
+        let mut tail: *const c_char = ptr::null();
+        let mut sources: Box<BTreeSet<String>> = Box::default();
+        // SAFETY: the connection is locked for the lifetime of `db`, `offset` stays within
+        // `sql`, which sqlite reads up to `len - offset` bytes of, and `sources` outlives
+        // the authorizer, which is removed right after the statement is prepared.
+        let is_empty = unsafe {
+            sqlite3_set_authorizer(
+                db,
+                Some(record_source),
+                &mut *sources as *mut BTreeSet<String> as *mut c_void,
+            );
+            let rc = sqlite3_prepare_v2(
+                db,
+                start.add(offset),
+                len - offset as i32,
+                &mut stmt,
+                &mut tail,
+            );
+            sqlite3_set_authorizer(db, None, ptr::null_mut());
+            if rc != SQLITE_OK {
+                return Err(error_message(db));
+            }
+            let is_empty = stmt.is_null();
+            sqlite3_finalize(stmt);
+            is_empty
+        };
+        let next = if tail.is_null() {
+            sql.len()
+        } else {
+            tail as usize - start as usize
+        };
+        if !is_empty {
+            let text = &sql[offset..next];
+            compiled.push(Compiled {
+                text,
+                functions: program_functions(db, text)?,
+                sources: *sources,
+            });
+        }
+        // nothing was consumed: only whitespace or comments were left
+        if next == offset {
+            break;
+        }
+        offset = next;
+    }
+    Ok(compiled)
+}
+
+/// The functions the program of the single statement `text` calls, read from its
+/// `EXPLAIN` listing.
+fn program_functions(db: *mut sqlite3, text: &str) -> Result<BTreeSet<String>, String> {
+    let explain = format!("EXPLAIN {}", text);
+    let len = i32::try_from(explain.len()).map_err(|_| "statement too long".to_string())?;
+    let mut stmt: *mut sqlite3_stmt = ptr::null_mut();
+    // SAFETY: the connection is locked for the lifetime of `db`, and sqlite reads `len`
+    // bytes of `explain`, which outlives the statement.
+    unsafe {
+        let rc = sqlite3_prepare_v2(
+            db,
+            explain.as_ptr() as *const c_char,
+            len,
+            &mut stmt,
+            ptr::null_mut(),
+        );
+        let functions = if rc == SQLITE_OK {
+            explained_functions(db, stmt)
+        } else {
+            Err(error_message(db))
+        };
+        sqlite3_finalize(stmt);
+        functions
+    }
+}
+
+/// # Safety
+///
+/// The connection of `db` must be locked, and `stmt` an `EXPLAIN` statement prepared on it.
+unsafe fn explained_functions(
+    db: *mut sqlite3,
+    stmt: *mut sqlite3_stmt,
+) -> Result<BTreeSet<String>, String> {
+    let mut functions = BTreeSet::new();
+    let rc = loop {
+        let rc = sqlite3_step(stmt);
+        if rc != SQLITE_ROW {
+            break rc;
+        }
+        let (opcode, p4) = (sqlite3_column_text(stmt, 1), sqlite3_column_text(stmt, 5));
+        if opcode.is_null() || p4.is_null() {
+            continue;
+        }
+        let opcode = CStr::from_ptr(opcode as *const c_char).to_bytes();
+        if opcode == b"Function" || opcode == b"PureFunc" {
+            // `name(argument count)`
+            let p4 = CStr::from_ptr(p4 as *const c_char).to_string_lossy();
+            let name = p4.split('(').next().unwrap_or_default();
+            functions.insert(name.to_ascii_lowercase());
+        }
+    };
+    if rc != SQLITE_DONE {
+        return Err(error_message(db));
+    }
+    Ok(functions)
+}
+
+unsafe extern "C" fn record_source(
+    ctx: *mut c_void,
+    action: c_int,
+    table: *const c_char,
+    _function: *const c_char,
+    _database: *const c_char,
+    trigger_or_view: *const c_char,
+) -> c_int {
+    let sources = &mut *(ctx as *mut BTreeSet<String>);
+    let source = match action {
+        SQLITE_FUNCTION => trigger_or_view,
+        SQLITE_INSERT => table,
+        _ => ptr::null(),
+    };
+    if !source.is_null() {
+        sources.insert(CStr::from_ptr(source).to_string_lossy().into_owned());
+    }
+    SQLITE_OK
+}
+
+unsafe fn error_message(db: *mut sqlite3) -> String {
+    CStr::from_ptr(sqlite3_errmsg(db))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The date and time functions that read the clock in `statement`, or in the definition
+/// of one of its sources.
+async fn clock_readers_of(
+    conn: &mut SqlxConnection,
+    statement: &Compiled<'_>,
+) -> Result<BTreeSet<String>, String> {
+    let mut readers = clock_readers(statement.text)?;
+    for source in &statement.sources {
+        let definitions: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT sql FROM sqlite_schema WHERE name = ? COLLATE NOCASE")
+                .bind(source)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(|err| err.to_string())?;
+        for definition in definitions.into_iter().flatten() {
+            readers.extend(clock_readers(&definition)?);
+        }
+    }
+    Ok(readers)
+}
+
+fn clock_readers(sql: &str) -> Result<BTreeSet<String>, String> {
+    let tokens = tokenize(sql)?;
+    let mut readers = BTreeSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let name = match token {
+            Token::Word(word) if word.quote_style.is_none() => word.value.to_ascii_lowercase(),
+            _ => continue,
+        };
+        if DATE_TIME_FUNCTIONS.contains(&name.as_str())
+            && matches!(tokens.get(i + 1), Some(Token::LParen))
+            && reads_clock(&name, &tokens[i + 2..])
+        {
+            readers.insert(name);
+        }
+    }
+    Ok(readers)
+}
+
+/// Fails on the first non-deterministic function `sql` calls.
+///
+/// Column defaults are skipped: they only run when a row is inserted.
+pub fn check_sql_deterministic(sql: &str) -> Result<(), String> {
+    let tokens = tokenize(sql)?;
+    let mut i = 0;
+    while i < tokens.len() {
+        let word = match &tokens[i] {
+            Token::Word(word) if word.quote_style.is_none() => word,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let name = word.value.to_ascii_lowercase();
+        if name == "default" {
+            i = skip_expression(&tokens, i + 1);
+            continue;
+        }
+        let is_call = matches!(tokens.get(i + 1), Some(Token::LParen));
+        let non_deterministic = if is_call {
+            NON_DETERMINISTIC_FUNCTIONS.contains(&name.as_str())
+                || (DATE_TIME_FUNCTIONS.contains(&name.as_str())
+                    && reads_clock(&name, &tokens[i + 2..]))
+        } else {
+            CLOCK_KEYWORDS.contains(&name.as_str())
+        };
+        if non_deterministic {
+            return Err(non_deterministic_error(&word.value, is_call));
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+fn non_deterministic_error(function: &str, is_call: bool) -> String {
+    format!(
+        "{}{} is non-deterministic and would give a different value on each node: \
+         compute the value on the client and bind it as a parameter, \
+         or allow non-deterministic statements for this request",
+        function,
+        if is_call { "()" } else { "" }
+    )
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, String> {
+    Ok(Tokenizer::new(&SQLiteDialect {}, sql)
+        .tokenize()
+        .map_err(|err| err.to_string())?
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect())
+}
+
+/// The index of the token following the default value starting at `start`: a single
+/// token, optionally signed, or a parenthesized expression.
+fn skip_expression(tokens: &[Token], start: usize) -> usize {
+    let mut i = start;
+    if matches!(tokens.get(i), Some(Token::Plus | Token::Minus)) {
+        i += 1;
+    }
+    if !matches!(tokens.get(i), Some(Token::LParen)) {
+        return i + 1;
+    }
+    let mut depth = 0;
+    for (j, token) in tokens.iter().enumerate().skip(i) {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 1 => return j + 1,
+            Token::RParen => depth -= 1,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// Whether the date and time function `name`, whose arguments start `args`, reads the
+/// clock.
+fn reads_clock(name: &str, args: &[Token]) -> bool {
+    let mut depth = 0;
+    let mut arg_count = 0;
+    let mut in_arg = false;
+    for token in args {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 0 => break,
+            Token::RParen => depth -= 1,
+            Token::Comma if depth == 0 => {
+                in_arg = false;
+                continue;
+            }
+            Token::SingleQuotedString(s) if s.eq_ignore_ascii_case("now") => return true,
+            _ => {}
+        }
+        if !in_arg {
+            in_arg = true;
+            arg_count += 1;
+        }
+    }
+    // the time value comes after the format for `strftime`
+    let time_value_index = if name == "strftime" { 1 } else { 0 };
+    arg_count <= time_value_index
+}
@@ -7,13 +7,27 @@ use sqlx_sqlite_cipher::Sqlite;
 pub type SqlxDb = Sqlite;
 
 use sqlx::prelude::*;
-use sqlx::{database::HasArguments, Column, Database, Pool, TypeInfo};
+use sqlx::{database::HasArguments, Column, Database, Pool, TypeInfo, ValueRef};
 use sqlx_core::types::chrono::{DateTime, Utc};
 
-use rxqlite_common::{Message, MessageResponse, Value};
-use sqlparser::ast::{Query, Statement};
-use sqlparser::dialect::GenericDialect;
-use sqlparser::parser::Parser;
+use rxqlite_common::{
+    ErrorKind, ExecuteResult, Message, MessageResponse, Parameters, RXQLiteError, Value,
+};
+
+mod backup;
+mod changeset;
+mod classify;
+mod determinism;
+mod parameters;
+mod request_ids;
+pub use backup::{backup_database, begin_read};
+pub use changeset::{capture_changeset, Capture};
+use changeset::apply_changeset;
+pub use classify::{is_message_write, is_message_write_on, is_query_write, is_query_write_on};
+pub use determinism::{check_deterministic, check_deterministic_on, check_sql_deterministic};
+use parameters::resolve_parameters;
+use request_ids::do_identified;
+pub use request_ids::{recorded_response, REQUESTS_TABLE, REQUEST_RETENTION};
 
 fn prepare_query<'q, DB: Database>(
     sql: &'q str,
@@ -28,12 +42,14 @@ where
     f64: Encode<'q, DB> + Type<DB>,
     DateTime<Utc>: Encode<'q, DB> + Type<DB>,
     Vec<u8>: Encode<'q, DB> + Type<DB>,
+    Option<i64>: Encode<'q, DB> + Type<DB>,
 {
     let mut query = sqlx::query(sql);
     for param in params {
         match param {
             Value::Null => {
-                return Err("passing null parameter considered an error".into());
+                // sqlite binds NULL the same way whatever the type of the `None`
+                query = query.bind(None::<i64>);
             }
             Value::Bool(b) => {
                 query = query.bind(b);
@@ -61,243 +77,378 @@ where
     Ok(query)
 }
 
+/// The error of a failed query, classified by the result code sqlite reported.
+pub(crate) fn sql_error(err: sqlx::Error) -> RXQLiteError {
+    match &err {
+        sqlx::Error::Database(db_err) => match db_err.code().and_then(|code| code.parse().ok()) {
+            Some(code) => RXQLiteError::sqlite(code, err.to_string()),
+            None => RXQLiteError::other(err.to_string()),
+        },
+        sqlx::Error::RowNotFound => RXQLiteError::new(ErrorKind::RowNotFound, err.to_string()),
+        _ => RXQLiteError::other(err.to_string()),
+    }
+}
+
 pub async fn do_sql(pool: &Pool<SqlxDb>, message: Message) -> MessageResponse {
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => return MessageResponse::Error(sql_error(err)),
+    };
     match message {
-        Message::Execute(sql, params) => {
-            let query = prepare_query(&sql, params);
-            if let Err(err) = &query {
-                let response_message = MessageResponse::Error(format!("{}", err));
-                return response_message;
-            }
-            let query = query.unwrap();
-            let res = query.execute(pool).await;
-            match res {
-                Ok(_) => {
-                    let response_message = MessageResponse::Rows(rxqlite_common::Rows::default());
-                    response_message
-                }
-                Err(err) => {
-                    let response_message = MessageResponse::Error(format!("{}", err));
-                    response_message
-                }
+        Message::WithRequestId {
+            request_id,
+            message,
+        } => do_identified(&mut conn, request_id, *message).await,
+        message => do_replicated(&mut conn, message).await,
+    }
+}
+
+/// Applies a write as replicated: either its statements or its changeset.
+async fn do_replicated(
+    conn: &mut <SqlxDb as Database>::Connection,
+    message: Message,
+) -> MessageResponse {
+    match message {
+        Message::Changeset { changeset, response } => {
+            match apply_changeset(conn, &changeset).await {
+                Ok(()) => *response,
+                Err(err) => MessageResponse::Error(err),
             }
         }
-        Message::Fetch(sql, params) => {
-            let query = prepare_query(&sql, params);
-            if let Err(err) = &query {
-                let response_message = MessageResponse::Error(format!("{}", err));
-                return response_message;
-            }
-            let query = query.unwrap();
-            let res = query.fetch_all(pool).await;
-            let mut resulting_rows: Vec<rxqlite_common::Row> = vec![];
-            match res {
-                Ok(rows) => {
-                    for row in rows.iter() {
-                        let mut resulting_row: Vec<rxqlite_common::Col> = vec![];
-                        let cols = row.len();
-                        for i in 0..cols {
-                            let col = row.column(i);
-                            let type_info = col.type_info();
-                            if type_info.is_null() {
-                                resulting_row.push(Value::Null);
-                            } else {
-                                //println!("TYPE: {}",type_info.name());
-                                match type_info.name() {
-                                    "BOOL" => {
-                                        let col: bool = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "INT" | "INTEGER" => {
-                                        let col: i64 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "TEXT" => {
-                                        let col: String = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "VARCHAR" => {
-                                        let col: String = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "FLOAT" => {
-                                        let col: f32 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "REAL" => {
-                                        let col: f64 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "DATETIME" => {
-                                        let col: DateTime<Utc> = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                        resulting_rows.push(resulting_row.into());
-                    }
-                    let response_message = MessageResponse::Rows(resulting_rows);
-                    response_message
-                }
-                Err(err) => {
-                    let response_message = MessageResponse::Error(format!("{}", err));
-                    response_message
-                }
+        message => do_message(conn, message).await,
+    }
+}
+
+async fn do_message(
+    conn: &mut <SqlxDb as Database>::Connection,
+    message: Message,
+) -> MessageResponse {
+    match message {
+        Message::Transaction(messages) => do_transaction(conn, messages).await,
+        Message::Batch(statements) => do_batch(conn, statements).await,
+        message => do_statement(conn, message).await,
+    }
+}
+
+// statements run inside a single sqlite transaction so that the batch costs one commit,
+// but sqlite only rolls back the failing statement, the other ones still get applied,
+// unless the failure rolled back the transaction: then none of them is.
+async fn do_batch(
+    conn: &mut <SqlxDb as Database>::Connection,
+    statements: Vec<(String, Parameters)>,
+) -> MessageResponse {
+    let mut transaction = match Connection::begin(conn).await {
+        Ok(transaction) => transaction,
+        Err(err) => return MessageResponse::Error(sql_error(err)),
+    };
+    let mut responses = Vec::with_capacity(statements.len());
+    for (i, (sql, params)) in statements.into_iter().enumerate() {
+        let (sql, params) = match resolve_parameters(&sql, params) {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                responses.push(MessageResponse::Error(RXQLiteError::invalid_request(err)));
+                continue;
             }
-        }
-        Message::FetchOne(sql, params) => {
-            let query = prepare_query(&sql, params);
-            if let Err(err) = &query {
-                let response_message = MessageResponse::Error(format!("{}", err));
-                return response_message;
+        };
+        let query = match prepare_query(&sql, params) {
+            Ok(query) => query,
+            Err(err) => {
+                responses.push(MessageResponse::Error(RXQLiteError::invalid_request(err)));
+                continue;
             }
-            let query = query.unwrap();
-            let res = query.fetch_one(pool).await;
-            match res {
-                Ok(row) => {
-                    let mut resulting_row: Vec<rxqlite_common::Col> = vec![];
-                    let cols = row.len();
-                    for i in 0..cols {
-                        let col = row.column(i);
-                        let type_info = col.type_info();
-                        if type_info.is_null() {
-                            resulting_row.push(Value::Null);
-                        } else {
-                            //println!("TYPE: {}",type_info.name());
-                            match type_info.name() {
-                                "BOOL" => {
-                                    let col: bool = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "INT" | "INTEGER" => {
-                                    let col: i64 = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "TEXT" => {
-                                    let col: String = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "VARCHAR" => {
-                                    let col: String = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "FLOAT" => {
-                                    let col: f32 = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "REAL" => {
-                                    let col: f64 = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                "DATETIME" => {
-                                    let col: DateTime<Utc> = row.get(i);
-                                    resulting_row.push(col.into());
-                                }
-                                _ => {}
-                            }
+        };
+        match query.execute(&mut *transaction).await {
+            Ok(res) => responses.push(MessageResponse::Executed(ExecuteResult {
+                rows_affected: res.rows_affected(),
+                last_insert_rowid: res.last_insert_rowid(),
+            })),
+            Err(err) => {
+                let err = sql_error(err);
+                // `OR ROLLBACK`, `RAISE(ROLLBACK)`, a full disk or an interrupt roll back the
+                // whole transaction, the statements that follow would be applied on their own
+                match is_autocommit(&mut transaction).await {
+                    Ok(false) => responses.push(MessageResponse::Error(err)),
+                    Ok(true) => {
+                        let err =
+                            err.context(format!("batch statement {} rolled back the batch", i));
+                        // sqlx still takes the transaction for open: begin one for it to roll back
+                        let res = match sqlx::query("BEGIN").execute(&mut *transaction).await {
+                            Ok(_) => transaction.rollback().await,
+                            Err(begin_err) => Err(begin_err),
+                        };
+                        if let Err(rollback_err) = res {
+                            return MessageResponse::Error(RXQLiteError {
+                                message: format!(
+                                    "{} (rollback failed: {})",
+                                    err.message, rollback_err
+                                ),
+                                ..err
+                            });
                         }
+                        return MessageResponse::Error(err);
                     }
-                    let response_message = MessageResponse::Rows(vec![resulting_row.into()]);
-                    response_message
-                }
-                Err(err) => {
-                    let response_message = MessageResponse::Error(format!("{}", err));
-                    response_message
+                    Err(autocommit_err) => return MessageResponse::Error(autocommit_err),
                 }
             }
         }
-        Message::FetchOptional(sql, params) => {
-            let query = prepare_query(&sql, params);
-            if let Err(err) = &query {
-                let response_message = MessageResponse::Error(format!("{}", err));
-                return response_message;
-            }
-            let query = query.unwrap();
-            let res = query.fetch_optional(pool).await;
-            match res {
-                Ok(row) => {
-                    if let Some(row) = row {
-                        let mut resulting_row: Vec<rxqlite_common::Col> = vec![];
-                        let cols = row.len();
-                        for i in 0..cols {
-                            let col = row.column(i);
-                            let type_info = col.type_info();
-                            if type_info.is_null() {
-                                resulting_row.push(Value::Null);
-                            } else {
-                                //println!("TYPE: {}",type_info.name());
-                                match type_info.name() {
-                                    "BOOL" => {
-                                        let col: bool = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "INT" | "INTEGER" => {
-                                        let col: i64 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "TEXT" => {
-                                        let col: String = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "VARCHAR" => {
-                                        let col: String = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "FLOAT" => {
-                                        let col: f32 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "REAL" => {
-                                        let col: f64 = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    "DATETIME" => {
-                                        let col: DateTime<Utc> = row.get(i);
-                                        resulting_row.push(col.into());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                        let response_message = MessageResponse::Rows(vec![resulting_row.into()]);
-                        response_message
-                    } else {
-                        let response_message = MessageResponse::Rows(Default::default());
-                        response_message
-                    }
-                }
-                Err(err) => {
-                    let response_message = MessageResponse::Error(format!("{}", err));
-                    response_message
-                }
+    }
+    match transaction.commit().await {
+        Ok(_) => MessageResponse::Batch(responses),
+        Err(err) => MessageResponse::Error(sql_error(err)),
+    }
+}
+
+/// Whether `conn` is outside of any transaction, as after a statement rolled it back.
+async fn is_autocommit(conn: &mut <SqlxDb as Database>::Connection) -> Result<bool, RXQLiteError> {
+    let mut handle = conn.lock_handle().await.map_err(sql_error)?;
+    // SAFETY: the connection is locked
+    Ok(unsafe { libsqlite3_sys::sqlite3_get_autocommit(handle.as_raw_handle().as_ptr()) } != 0)
+}
+
+/// How many rows the statements run on `conn` changed since it was opened.
+async fn total_changes(conn: &mut <SqlxDb as Database>::Connection) -> Result<i64, RXQLiteError> {
+    let mut handle = conn.lock_handle().await.map_err(sql_error)?;
+    // SAFETY: the connection is locked
+    Ok(unsafe { libsqlite3_sys::sqlite3_total_changes64(handle.as_raw_handle().as_ptr()) })
+}
+
+/// What the last statement run on `conn` changed, `None` when no row changed since
+/// `total_changes` were counted: sqlite keeps reporting the last write until another one.
+async fn changes_since(
+    conn: &mut <SqlxDb as Database>::Connection,
+    total_changes: i64,
+) -> Result<Option<ExecuteResult>, RXQLiteError> {
+    let mut handle = conn.lock_handle().await.map_err(sql_error)?;
+    let db = handle.as_raw_handle().as_ptr();
+    // SAFETY: the connection is locked
+    unsafe {
+        if libsqlite3_sys::sqlite3_total_changes64(db) == total_changes {
+            return Ok(None);
+        }
+        Ok(Some(ExecuteResult {
+            rows_affected: libsqlite3_sys::sqlite3_changes64(db) as u64,
+            last_insert_rowid: libsqlite3_sys::sqlite3_last_insert_rowid(db),
+        }))
+    }
+}
+
+async fn do_transaction(
+    conn: &mut <SqlxDb as Database>::Connection,
+    messages: Vec<Message>,
+) -> MessageResponse {
+    let mut transaction = match Connection::begin(conn).await {
+        Ok(transaction) => transaction,
+        Err(err) => return MessageResponse::Error(sql_error(err)),
+    };
+    let mut responses = Vec::with_capacity(messages.len());
+    for (i, message) in messages.into_iter().enumerate() {
+        let response = do_statement(&mut transaction, message).await;
+        if let MessageResponse::Error(err) = response {
+            let err = err.context(format!("transaction statement {}", i));
+            if let Err(rollback_err) = transaction.rollback().await {
+                return MessageResponse::Error(RXQLiteError {
+                    message: format!("{} (rollback failed: {})", err.message, rollback_err),
+                    ..err
+                });
             }
+            return MessageResponse::Error(err);
         }
+        responses.push(response);
+    }
+    match transaction.commit().await {
+        Ok(_) => MessageResponse::Transaction(responses),
+        Err(err) => MessageResponse::Error(sql_error(err)),
     }
 }
 
-fn is_for_update_or_share(query: &Box<Query>) -> bool {
-    !query.locks.is_empty()
+// values are decoded from their runtime storage class, the declared type of the column is
+// only used to recover the types sqlite stores with another class (booleans and datetimes).
+fn decode_row(row: &<SqlxDb as Database>::Row) -> Result<Vec<rxqlite_common::Col>, String> {
+    let mut resulting_row: Vec<rxqlite_common::Col> = Vec::with_capacity(row.len());
+    for i in 0..row.len() {
+        let value = decode_value(row, i)
+            .map_err(|err| format!("column {} ({}): {}", i, row.column(i).name(), err))?;
+        resulting_row.push(value);
+    }
+    Ok(resulting_row)
 }
 
-pub fn is_query_write(sql: &str) -> anyhow::Result<bool> {
-    let ast = Parser::parse_sql(&GenericDialect, sql)?;
-    for stmt in ast {
-        match stmt {
-            Statement::Query(query) => {
-                if is_for_update_or_share(&query) {
-                    return Ok(true);
-                } else {
-                }
-            }
-            Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. } => {
-                return Ok(true);
-            }
-            _ => {
-                return Ok(true);
+fn decode_value(row: &<SqlxDb as Database>::Row, i: usize) -> Result<Value, sqlx::Error> {
+    let raw = row.try_get_raw(i)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+    let storage_class = raw.type_info().name().to_string();
+    let declared = row.column(i).type_info().name();
+    match (storage_class.as_str(), declared) {
+        ("INTEGER", "BOOLEAN") => Ok(Value::Bool(row.try_get_unchecked(i)?)),
+        ("INTEGER" | "REAL" | "TEXT", "DATETIME") => {
+            match row.try_get_unchecked::<DateTime<Utc>, _>(i) {
+                Ok(dt) => Ok(Value::DateTime(dt)),
+                // not something sqlx recognizes as a datetime: keep the value as stored
+                Err(_) => decode_storage_class(row, i, &storage_class),
             }
         }
+        _ => decode_storage_class(row, i, &storage_class),
+    }
+}
+
+fn decode_storage_class(
+    row: &<SqlxDb as Database>::Row,
+    i: usize,
+    storage_class: &str,
+) -> Result<Value, sqlx::Error> {
+    match storage_class {
+        "INTEGER" => Ok(Value::Int(row.try_get_unchecked(i)?)),
+        "REAL" => Ok(Value::F64(row.try_get_unchecked(i)?)),
+        "TEXT" => Ok(Value::String(row.try_get_unchecked(i)?)),
+        "BLOB" => Ok(Value::Blob(row.try_get_unchecked(i)?)),
+        other => Err(sqlx::Error::ColumnDecode {
+            index: format!("{}", i),
+            source: format!("unsupported sqlite storage class {}", other).into(),
+        }),
+    }
+}
+
+// the header is taken from the columns of the rows, or from the prepared statement when the
+// query matches no row: describing it means running the query planner again.
+async fn describe_columns(
+    conn: &mut <SqlxDb as Database>::Connection,
+    sql: &str,
+    rows: &[<SqlxDb as Database>::Row],
+) -> Vec<rxqlite_common::Column> {
+    if let Some(row) = rows.first() {
+        let columns = row
+            .columns()
+            .iter()
+            .map(|col| rxqlite_common::Column {
+                name: col.name().to_string(),
+                decl_type: decl_type(col.type_info()),
+                nullable: None,
+            })
+            .collect();
+        return columns;
+    }
+    let describe = match conn.describe(sql).await {
+        Ok(describe) => describe,
+        // a schema change such as a `CREATE TABLE` may not prepare again once it ran,
+        // it has no columns anyway
+        Err(_) => return vec![],
+    };
+    describe
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(i, col)| rxqlite_common::Column {
+            name: col.name().to_string(),
+            decl_type: decl_type(col.type_info()),
+            nullable: describe.nullable(i),
+        })
+        .collect()
+}
+
+fn decl_type(type_info: &<SqlxDb as Database>::TypeInfo) -> Option<String> {
+    if type_info.is_null() {
+        None
+    } else {
+        Some(type_info.name().to_string())
+    }
+}
+
+async fn rows_response(
+    conn: &mut <SqlxDb as Database>::Connection,
+    sql: &str,
+    rows: &[<SqlxDb as Database>::Row],
+) -> MessageResponse {
+    let columns = describe_columns(conn, sql, rows).await;
+    let mut resulting_rows = rxqlite_common::Rows::new(columns);
+    for row in rows {
+        match decode_row(row) {
+            Ok(resulting_row) => resulting_rows.add_row(resulting_row),
+            Err(err) => return MessageResponse::Error(RXQLiteError::new(ErrorKind::Decode, err)),
+        }
+    }
+    MessageResponse::Rows(resulting_rows)
+}
+
+/// What a single statement answers.
+enum Answer {
+    Executed,
+    All,
+    One,
+    Optional,
+}
+
+async fn do_statement(
+    conn: &mut <SqlxDb as Database>::Connection,
+    message: Message,
+) -> MessageResponse {
+    let (answer, sql, params) = match message {
+        Message::Transaction(_) => {
+            return MessageResponse::Error(RXQLiteError::invalid_request(
+                "nested transactions are not supported",
+            ))
+        }
+        Message::Batch(_) => {
+            return MessageResponse::Error(RXQLiteError::invalid_request(
+                "a batch can't be part of a transaction",
+            ))
+        }
+        Message::Changeset { .. } => {
+            return MessageResponse::Error(RXQLiteError::invalid_request(
+                "a changeset can't be part of a transaction",
+            ))
+        }
+        Message::WithRequestId { .. } => {
+            return MessageResponse::Error(RXQLiteError::invalid_request(
+                "an identified write can't be part of a transaction",
+            ))
+        }
+        Message::Execute(sql, params) => (Answer::Executed, sql, params),
+        Message::Fetch(sql, params) => (Answer::All, sql, params),
+        Message::FetchOne(sql, params) => (Answer::One, sql, params),
+        Message::FetchOptional(sql, params) => (Answer::Optional, sql, params),
+    };
+    let (sql, params) = match resolve_parameters(&sql, params) {
+        Ok(resolved) => resolved,
+        Err(err) => return MessageResponse::Error(RXQLiteError::invalid_request(err)),
+    };
+    let query = match prepare_query(&sql, params) {
+        Ok(query) => query,
+        Err(err) => return MessageResponse::Error(RXQLiteError::invalid_request(err)),
+    };
+    if let Answer::Executed = answer {
+        return match query.execute(&mut *conn).await {
+            Ok(res) => MessageResponse::Executed(ExecuteResult {
+                rows_affected: res.rows_affected(),
+                last_insert_rowid: res.last_insert_rowid(),
+            }),
+            Err(err) => MessageResponse::Error(sql_error(err)),
+        };
+    }
+    let total_changes = match total_changes(conn).await {
+        Ok(total_changes) => total_changes,
+        Err(err) => return MessageResponse::Error(err),
+    };
+    let rows = match answer {
+        Answer::One => query.fetch_one(&mut *conn).await.map(|row| vec![row]),
+        Answer::Optional => query
+            .fetch_optional(&mut *conn)
+            .await
+            .map(|row| row.into_iter().collect()),
+        _ => query.fetch_all(&mut *conn).await,
+    };
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(err) => return MessageResponse::Error(sql_error(err)),
+    };
+    let mut response = rows_response(conn, &sql, &rows).await;
+    // a write returning rows, such as an `INSERT ... RETURNING`, tells what it changed too
+    if let MessageResponse::Rows(rows) = &mut response {
+        match changes_since(conn, total_changes).await {
+            Ok(executed) => rows.set_executed(executed),
+            Err(err) => return MessageResponse::Error(err),
+        }
     }
-    Ok(false)
+    response
 }
@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use rxqlite_common::{Parameters, Value};
+
+/// Turns the parameters of a statement into the positional values sqlx binds.
+///
+/// sqlx only binds `?`, `?NNN` and `$NNN` placeholders, so named placeholders are
+/// rewritten as `?NNN`, numbered in the order the names first appear in `sql`.
+pub(crate) fn resolve_parameters(
+    sql: &str,
+    params: Parameters,
+) -> Result<(Cow<'_, str>, Vec<Value>), String> {
+    match params {
+        Parameters::Positional(values) => Ok((Cow::Borrowed(sql), values)),
+        Parameters::Named(values) => bind_named(sql, values),
+    }
+}
+
+fn bind_named(
+    sql: &str,
+    values: BTreeMap<String, Value>,
+) -> Result<(Cow<'_, str>, Vec<Value>), String> {
+    let mut rewritten = String::with_capacity(sql.len());
+    // placeholders in order of first appearance
+    let mut names: Vec<&str> = vec![];
+    let mut bound = vec![];
+    let mut used = BTreeSet::new();
+    let mut last = 0;
+    for (start, end) in placeholders(sql) {
+        let placeholder = &sql[start..end];
+        let (prefix, name) = placeholder.split_at(1);
+        if prefix == "?" || name.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!(
+                "positional placeholder {} can't be mixed with named parameters",
+                placeholder
+            ));
+        }
+        let position = match names.iter().position(|p| *p == placeholder) {
+            Some(position) => position,
+            None => {
+                // `:id` and `@id` may both take the value of `id`
+                let (key, value) = values
+                    .get_key_value(placeholder)
+                    .or_else(|| values.get_key_value(name))
+                    .ok_or_else(|| format!("no value for named parameter {}", placeholder))?;
+                used.insert(key.as_str());
+                names.push(placeholder);
+                bound.push(value.clone());
+                names.len() - 1
+            }
+        };
+        rewritten.push_str(&sql[last..start]);
+        rewritten.push_str(&format!("?{}", position + 1));
+        last = end;
+    }
+    rewritten.push_str(&sql[last..]);
+    if let Some(name) = values.keys().find(|name| !used.contains(name.as_str())) {
+        return Err(format!(
+            "named parameter {} doesn't appear in the statement",
+            name
+        ));
+    }
+    Ok((Cow::Owned(rewritten), bound))
+}
+
+fn is_identifier_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80
+}
+
+/// The byte ranges of the bind placeholders of `sql`, skipping string literals,
+/// quoted identifiers and comments.
+fn placeholders(sql: &str) -> Vec<(usize, usize)> {
+    let bytes = sql.as_bytes();
+    let mut found = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"' | b'`') => {
+                // a doubled quote is an escaped quote, it is handled as two literals in a row
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'[' => {
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            b'?' | b':' | b'@' | b'$' if i == 0 || !is_identifier_byte(bytes[i - 1]) => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && is_identifier_byte(bytes[i]) && bytes[i] != b'$' {
+                    i += 1;
+                }
+                // a lone `:`, `@` or `$` isn't a placeholder
+                if bytes[start] == b'?' || i > start + 1 {
+                    found.push((start, i));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    found
+}
@@ -0,0 +1,154 @@
+//! Writes identified by a [`RequestId`] are applied once. Their responses are recorded in
+//! a table of the database, so that snapshots carry them along with the data, and a node
+//! applying a write with a recorded id again answers the recorded response instead.
+//!
+//! Every node records the same responses in the same order, and forgets the oldest ones
+//! the same way, so that nodes agree on which writes they already applied.
+
+use rxqlite_common::{Message, MessageResponse, RXQLiteError, RequestId};
+use sqlx::{Connection, Database, Pool};
+
+use crate::changeset::runs_in_transaction;
+use crate::{sql_error, SqlxDb};
+
+type SqlxConnection = <SqlxDb as Database>::Connection;
+
+/// The table the responses of identified writes are recorded in.
+pub const REQUESTS_TABLE: &str = "_rxqlite_requests_";
+
+/// How many of the last identified writes have their responses recorded.
+pub const REQUEST_RETENTION: i64 = 10_000;
+
+/// The recorded response of the write identified by `request_id`, if it was applied and
+/// isn't too old to be remembered.
+pub async fn recorded_response(
+    pool: &Pool<SqlxDb>,
+    request_id: &RequestId,
+) -> Result<Option<MessageResponse>, RXQLiteError> {
+    let mut conn = pool.acquire().await.map_err(sql_error)?;
+    let has_table: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1")
+            .bind(REQUESTS_TABLE)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(sql_error)?;
+    if has_table == 0 {
+        return Ok(None);
+    }
+    find_response(&mut conn, request_id).await
+}
+
+/// Applies `message` unless the write identified by `request_id` was already applied,
+/// answering its recorded response then.
+pub(crate) async fn do_identified(
+    conn: &mut SqlxConnection,
+    request_id: RequestId,
+    message: Message,
+) -> MessageResponse {
+    if let Err(err) = create_table(conn).await {
+        return MessageResponse::Error(err);
+    }
+    match find_response(conn, &request_id).await {
+        Ok(Some(response)) => return response,
+        Ok(None) => {}
+        Err(err) => return MessageResponse::Error(err),
+    }
+    if !runs_in_transaction(&message) {
+        // the write ends transactions itself: its response is recorded once it's applied
+        let response = crate::do_replicated(conn, message).await;
+        return match record_response(conn, &request_id, &response).await {
+            Ok(()) => response,
+            Err(_) if matches!(response, MessageResponse::Error(_)) => response,
+            // the write is applied but sending it again would apply it again:
+            // the error must not be one the client retries
+            Err(err) => MessageResponse::Error(RXQLiteError::other(format!(
+                "the write was applied but its request id couldn't be recorded: {}",
+                err.message
+            ))),
+        };
+    }
+    let mut transaction = match conn.begin().await {
+        Ok(transaction) => transaction,
+        Err(err) => return MessageResponse::Error(sql_error(err)),
+    };
+    let response = crate::do_replicated(&mut transaction, message).await;
+    if let Err(err) = record_response(&mut transaction, &request_id, &response).await {
+        let _ = transaction.rollback().await;
+        return MessageResponse::Error(err);
+    }
+    match transaction.commit().await {
+        Ok(()) => response,
+        Err(err) => MessageResponse::Error(sql_error(err)),
+    }
+}
+
+async fn create_table(conn: &mut SqlxConnection) -> Result<(), RXQLiteError> {
+    let sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            id INTEGER PRIMARY KEY,
+            client_id INTEGER NOT NULL,
+            sequence INTEGER NOT NULL,
+            response TEXT NOT NULL,
+            UNIQUE (client_id, sequence)
+        )",
+        REQUESTS_TABLE
+    );
+    sqlx::query(&sql)
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(sql_error)
+}
+
+async fn find_response(
+    conn: &mut SqlxConnection,
+    request_id: &RequestId,
+) -> Result<Option<MessageResponse>, RXQLiteError> {
+    let sql = format!(
+        "SELECT response FROM {} WHERE client_id = ?1 AND sequence = ?2",
+        REQUESTS_TABLE
+    );
+    // sqlite integers are signed: ids are stored with the same bits
+    let response: Option<String> = sqlx::query_scalar(&sql)
+        .bind(request_id.client_id as i64)
+        .bind(request_id.sequence as i64)
+        .fetch_optional(conn)
+        .await
+        .map_err(sql_error)?;
+    match response {
+        Some(response) => serde_json::from_str(&response)
+            .map(Some)
+            .map_err(|err| RXQLiteError::other(err.to_string())),
+        None => Ok(None),
+    }
+}
+
+async fn record_response(
+    conn: &mut SqlxConnection,
+    request_id: &RequestId,
+    response: &MessageResponse,
+) -> Result<(), RXQLiteError> {
+    let response =
+        serde_json::to_string(response).map_err(|err| RXQLiteError::other(err.to_string()))?;
+    let sql = format!(
+        "INSERT INTO {} (client_id, sequence, response) VALUES (?1, ?2, ?3)",
+        REQUESTS_TABLE
+    );
+    sqlx::query(&sql)
+        .bind(request_id.client_id as i64)
+        .bind(request_id.sequence as i64)
+        .bind(response)
+        .execute(&mut *conn)
+        .await
+        .map_err(sql_error)?;
+    let sql = format!(
+        "DELETE FROM {0} WHERE id <= (SELECT MAX(id) FROM {0}) - ?1",
+        REQUESTS_TABLE
+    );
+    sqlx::query(&sql)
+        .bind(REQUEST_RETENTION)
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(sql_error)
+}
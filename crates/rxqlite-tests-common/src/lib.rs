@@ -75,6 +75,25 @@ impl TestClusterManager {
         executable_path: P,
         host: &str,
         tls_config: Option<TestTlsConfig>,
+    ) -> anyhow::Result<Self> {
+        Self::new_with_args(
+            instance_count,
+            working_directory,
+            executable_path,
+            host,
+            tls_config,
+            &[],
+        )
+    }
+    /// Like [`TestClusterManager::new`], initializing every node with `extra_args`
+    /// in addition to the usual ones.
+    pub fn new_with_args<P: AsRef<Path>>(
+        instance_count: usize,
+        working_directory: P,
+        executable_path: P,
+        host: &str,
+        tls_config: Option<TestTlsConfig>,
+        extra_args: &[&str],
     ) -> anyhow::Result<Self> {
         std::fs::create_dir_all(&working_directory)?;
         let base_port = PORT_MANAGER
@@ -131,6 +150,7 @@ impl TestClusterManager {
                 .arg(&rpc_addr)
                 .arg("--notifications-addr")
                 .arg(&notifications_addr)
+                .args(extra_args)
                 .current_dir(&working_directory);
 
             if tls_config.is_some() {
@@ -184,6 +204,8 @@ impl TestClusterManager {
 {
               let pid = child.id() as i32;
               kill(Pid::from_raw(pid), Signal::SIGINT)?;
+              // a suspended node handles the signal once it runs again
+              kill(Pid::from_raw(pid), Signal::SIGCONT)?;
 }
 #[cfg(not(target_os = "linux"))]
 {
@@ -209,6 +231,47 @@ impl TestClusterManager {
         }
         Ok(())
     }
+    /// Stops a node, waiting for its process to exit.
+    pub fn kill(&mut self, node_id: u64) -> anyhow::Result<()> {
+        let instance = self
+            .instances
+            .get_mut(&node_id)
+            .ok_or_else(|| anyhow::anyhow!("unknown node {}", node_id))?;
+        if let Some(mut child) = instance.child.take() {
+#[cfg(target_os = "linux")]
+{
+            let pid = Pid::from_raw(child.id() as i32);
+            kill(pid, Signal::SIGINT)?;
+            kill(pid, Signal::SIGCONT)?;
+}
+#[cfg(not(target_os = "linux"))]
+{
+            child.kill()?;
+}
+            child.wait()?;
+        }
+        Ok(())
+    }
+    /// Stops the process of a node without killing it, as if it was cut off from the
+    /// cluster, until [`TestClusterManager::resume`] is called.
+    #[cfg(target_os = "linux")]
+    pub fn suspend(&self, node_id: u64) -> anyhow::Result<()> {
+        self.signal(node_id, Signal::SIGSTOP)
+    }
+    #[cfg(target_os = "linux")]
+    pub fn resume(&self, node_id: u64) -> anyhow::Result<()> {
+        self.signal(node_id, Signal::SIGCONT)
+    }
+    #[cfg(target_os = "linux")]
+    fn signal(&self, node_id: u64, signal: Signal) -> anyhow::Result<()> {
+        let child = self
+            .instances
+            .get(&node_id)
+            .and_then(|instance| instance.child.as_ref())
+            .ok_or_else(|| anyhow::anyhow!("node {} isn't running", node_id))?;
+        kill(Pid::from_raw(child.id() as i32), signal)?;
+        Ok(())
+    }
     pub fn start(&mut self) -> anyhow::Result<()> {
         for (node_id, instance) in self.instances.iter_mut() {
             let mut cmd = Command::new(&self.executable);
@@ -223,6 +286,23 @@ impl TestClusterManager {
         }
         Ok(())
     }
+    /// Starts a node stopped with [`TestClusterManager::kill`], with `extra_args` in
+    /// addition to its id.
+    pub fn start_with_args(&mut self, node_id: u64, extra_args: &[&str]) -> anyhow::Result<()> {
+        let instance = self
+            .instances
+            .get_mut(&node_id)
+            .ok_or_else(|| anyhow::anyhow!("unknown node {}", node_id))?;
+        let child = Command::new(&self.executable)
+            .arg("--test-node")
+            .arg("--id")
+            .arg(node_id.to_string())
+            .args(extra_args)
+            .current_dir(&self.working_directory)
+            .spawn()?;
+        instance.child = Some(child);
+        Ok(())
+    }
     pub fn clean_directories(&self) -> anyhow::Result<()> {
         if self.keep_temp_directories {
             return Ok(());
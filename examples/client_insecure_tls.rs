@@ -22,7 +22,7 @@ async fn main() {
           birth_date DATETIME NOT NULL
           )"
             .into(),
-            vec![],
+            vec![].into(),
         );
         let response = client.sql(&message).await;
         println!("Server response: {:#?}", response);
@@ -32,7 +32,7 @@ async fn main() {
             let birth_date = Utc::now();
             let message = Message::Execute(
                 "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".into(),
-                vec![name.into(), birth_date.into()],
+                vec![name.into(), birth_date.into()].into(),
             );
             let response = client.sql(&message).await;
             println!("Server answer: {:?}", response);
@@ -40,7 +40,7 @@ async fn main() {
 
         let message = Message::Fetch(
             "SELECT name,birth_date from _test_user_ where name = ?".into(),
-            vec!["ha-10".into()],
+            vec!["ha-10".into()].into(),
         );
         let response = client.sql(&message).await;
         match response {
@@ -68,7 +68,7 @@ async fn main() {
                 eprintln!("error : {}", err);
             }
         }
-        let message = Message::Fetch("SELECT * from _test_user_".into(), vec![]);
+        let message = Message::Fetch("SELECT * from _test_user_".into(), vec![].into());
         let response = client.sql(&message).await;
         match response {
             Ok(response) => match response.data {
@@ -102,13 +102,13 @@ birth_date: {}
             let name = format!("ha-{}", i);
             let message = Message::Execute(
                 "DELETE FROM _test_user_ where name = ?".into(),
-                vec![name.into()],
+                vec![name.into()].into(),
             );
             let response = client.sql(&message).await;
             println!("Server answer: {:?}", response);
         }
 
-        let message = Message::Execute("DROP TABLE _test_user_ ".into(), vec![]);
+        let message = Message::Execute("DROP TABLE _test_user_ ".into(), vec![].into());
         let response = client.sql(&message).await;
         println!("Server answer: {:?}", response);
     }
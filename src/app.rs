@@ -1,11 +1,42 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use openraft::Config;
+use tokio::sync::watch;
+use tokio::sync::Mutex;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 
+use crate::compaction::Compaction;
 use crate::sqlite_store::*;
 use crate::ExampleRaft;
 use crate::NodeId;
+use crate::ReadMode;
+use crate::WriteMode;
+/// The last append entries request this node accepted from a leader.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderContact {
+    pub received_at: Instant,
+    /// The index of the last entry the leader had committed when it sent the request.
+    pub leader_commit: Option<u64>,
+}
+
+/// The time until which the leader answers `sql-consistent` reads without checking with a
+/// quorum that it still is the leader.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderLease {
+    /// The term this node was the leader of when a quorum acknowledged it.
+    pub term: u64,
+    pub expires_at: Instant,
+}
+
+/// Sends the `sql-consistent` reads a follower receives to the leader, over http or https
+/// depending on the tls configuration of the node.
+pub struct ReadForwarder {
+    pub client: reqwest::Client,
+    pub use_tls: bool,
+}
+
 // Representation of an application state. This struct can be shared around to share
 // instances of raft, store and more.
 pub struct App {
@@ -16,4 +47,17 @@ pub struct App {
     //pub key_values: Arc<RwLock<BTreeMap<String, String>>>,
     pub sqlite_and_path: Arc<RwLock<SqliteAndPath>>,
     pub config: Arc<Config>,
+    pub write_mode: WriteMode,
+    // held from the capture of a changeset until it is applied
+    pub write_lock: Mutex<()>,
+    // tells how stale the database of a follower may be
+    pub leader_contact: watch::Sender<Option<LeaderContact>>,
+    pub read_mode: ReadMode,
+    // how long a lease lasts from the time this node asks a quorum to acknowledge it
+    pub lease_duration: Duration,
+    pub leader_lease: watch::Sender<Option<LeaderLease>>,
+    // set when reads arriving at a follower are forwarded rather than redirected
+    pub read_forwarder: Option<ReadForwarder>,
+    // when this node builds snapshots beyond what openraft checks itself
+    pub compaction: Compaction,
 }
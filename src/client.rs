@@ -2,14 +2,16 @@ use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use openraft::error::Fatal;
 use openraft::error::NetworkError;
 use openraft::error::RPCError;
 use openraft::error::RemoteError;
+use openraft::error::Unreachable;
 use openraft::RaftMetrics;
 use openraft::TryAsRef;
 use reqwest::{Client, ClientBuilder};
 use serde::de::DeserializeOwned;
-//use serde::Deserialize;
+use serde::Deserialize;
 use serde::Serialize;
 
 use tokio::time::{timeout, Duration};
@@ -26,9 +28,12 @@ use tokio::io::{ReadHalf, WriteHalf};
 use tokio_rustls::rustls::ClientConfig;
 use tokio_rustls::rustls::RootCertStore;
 
+use crate::compaction::CompactionMetrics;
+use crate::network::management::Empty;
 use crate::notifications::{NotificationEvent, NotificationRequest};
 use serde_json::{from_slice, to_vec};
 
+use crate::retry::{Attempts, RetryPolicy};
 use crate::sqlite_store::Request;
 use crate::typ;
 use crate::Node;
@@ -92,7 +97,10 @@ impl tokio_rustls::rustls::client::danger::ServerCertVerifier for AllowAnyCertVe
 
 //use crate::TypeConfig;
 
-use rxqlite_common::{Message, MessageResponse, RSQliteClientTlsConfig, Rows, Value};
+use rxqlite_common::{
+    ErrorKind, ExecuteResult, Message, MessageResponse, Parameters, RSQliteClientTlsConfig,
+    RXQLiteError, RequestOptions, Rows,
+};
 
 use crate::ConnectOptions;
 
@@ -102,6 +110,7 @@ pub struct RXQLiteClientBuilder {
     //tls_config: Option<RSQliteClientTlsConfig>,
     use_tls: bool,
     accept_invalid_certificates: bool,
+    retry_policy: RetryPolicy,
 }
 
 impl RXQLiteClientBuilder {
@@ -112,6 +121,7 @@ impl RXQLiteClientBuilder {
             //tls_config: None,
             use_tls: false,
             accept_invalid_certificates: false,
+            retry_policy: Default::default(),
         }
     }
     pub fn tls_config(mut self, tls_config: Option<RSQliteClientTlsConfig>) -> Self {
@@ -132,6 +142,10 @@ impl RXQLiteClientBuilder {
         self.accept_invalid_certificates = accept_invalid_certificates;
         self
     }
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
     pub fn build(self) -> RXQLiteClient {
         let mut inner = ClientBuilder::new();
         let use_tls = if self.use_tls {
@@ -145,11 +159,13 @@ impl RXQLiteClientBuilder {
         let inner = inner.build().unwrap();
         RXQLiteClient {
             node: Arc::new(Mutex::new((self.node_id, self.node_addr.clone()))),
+            nodes: Arc::new(Mutex::new(vec![self.node_addr.clone()])),
             leader: Arc::new(Mutex::new((self.node_id, self.node_addr))),
             inner,
             use_tls,
             notification_stream: None,
             accept_invalid_certificates: self.accept_invalid_certificates,
+            retry_policy: self.retry_policy,
         }
     }
 }
@@ -265,6 +281,12 @@ impl NetStream {
     }
 }
 
+/// The part of the metrics of a node about its compaction.
+#[derive(Serialize, Deserialize)]
+struct CompactionReport {
+    compaction: CompactionMetrics,
+}
+
 pub struct RXQLiteClient {
     /// The leader node to send request to.
     ///
@@ -276,6 +298,11 @@ pub struct RXQLiteClient {
     /// Mainly used to get node metrics.
     pub node: Arc<Mutex<(NodeId, String)>>,
 
+    /// The api addresses of the nodes this client knows of, asked for the leader when it
+    /// can't be reached: the node it was created for, the seeds it was given, and the
+    /// members of the cluster it learned from them.
+    pub nodes: Arc<Mutex<Vec<String>>>,
+
     pub inner: Client,
 
     pub use_tls: bool,
@@ -283,6 +310,9 @@ pub struct RXQLiteClient {
     pub accept_invalid_certificates: bool,
 
     pub notification_stream: Option<NetStream>,
+
+    /// How requests are sent again after an error.
+    pub retry_policy: RetryPolicy,
 }
 
 impl RXQLiteClient {
@@ -308,13 +338,17 @@ impl RXQLiteClient {
             options.leader_id,
             format!("{}:{}", options.leader_host, options.leader_port),
         )));
+        let mut nodes = vec![format!("{}:{}", options.leader_host, options.leader_port)];
+        nodes.extend(options.seeds.iter().cloned());
         Self {
             node,
+            nodes: Arc::new(Mutex::new(nodes)),
             leader,
             inner,
             use_tls: options.tls_config.is_some(),
             notification_stream: None,
             accept_invalid_certificates,
+            retry_policy: options.retry_policy.clone(),
         }
     }
 
@@ -322,11 +356,13 @@ impl RXQLiteClient {
     pub fn new(node_id: NodeId, node_addr: String) -> Self {
         Self {
             node: Arc::new(Mutex::new((node_id, node_addr.clone()))),
+            nodes: Arc::new(Mutex::new(vec![node_addr.clone()])),
             leader: Arc::new(Mutex::new((node_id, node_addr))),
             inner: Client::new(),
             use_tls: false,
             notification_stream: None,
             accept_invalid_certificates: false,
+            retry_policy: Default::default(),
         }
     }
 
@@ -345,45 +381,25 @@ impl RXQLiteClient {
         self.send_rpc_to_leader("api/sql", Some(req)).await
     }
     
+    /// Like [`RXQLiteClient::sql`], sending the request up to `retries` more times while
+    /// the cluster has no leader, `delay_between_retries` apart.
     pub async fn sql_with_retries_and_delay(
         &self,
         req: &Request,
-        mut retries: usize,
+        retries: usize,
         delay_between_retries: Duration,
     ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
-        retries += 1;
-        loop {
-          match self.send_rpc_to_leader("api/sql", Some(req)).await {
-            Ok(res)=>return Ok(res),
-            Err(rpc_err)=> {
-              if let RPCError::RemoteError(remote_err) = &rpc_err {
-                let raft_err: &typ::RaftError<_> = &remote_err.source;
-
-                if let Some(typ::ForwardToLeader {
-                    leader_id,   //: Some(leader_id),
-                    leader_node : _, //: Some(leader_node),
-                    ..
-                }) = raft_err.forward_to_leader() {
-                  if leader_id.is_some() {
-                    return Err(rpc_err);
-                  } else {
-                    retries-=1;
-                    if retries == 0 {
-                      return Err(rpc_err);
-                    }
-                    tokio::time::sleep(delay_between_retries).await;
-                  }
-                } else {
-                  return Err(rpc_err);
-                }
-              } else {
-                return Err(rpc_err);
-              }
-            }
-          }
-        }
+        let retry_policy = RetryPolicy {
+            max_attempts: retries + 1,
+            initial_backoff: delay_between_retries,
+            max_backoff: delay_between_retries,
+            jitter: false,
+            ..self.retry_policy.clone()
+        };
+        self.send_rpc_to_leader_with_policy("api/sql", Some(req), &retry_policy)
+            .await
     }
-    
+
     pub async fn consistent_sql(
         &self,
         req: &Request,
@@ -391,32 +407,66 @@ impl RXQLiteClient {
         self.send_rpc_to_leader("api/sql-consistent", Some(req))
             .await
     }
+    pub async fn consistent_sql_with_options(
+        &self,
+        req: &Request,
+        options: &RequestOptions,
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        self.send_rpc_to_leader(&sql_uri("api/sql-consistent", options), Some(req))
+            .await
+    }
+    /// Send a request to the node this client was created for rather than to the leader,
+    /// so that a follower answers reads from its own database. Reads wait for the freshness
+    /// `options` ask for, see [`RequestOptions::min_applied_index`] and
+    /// [`RequestOptions::max_staleness`].
+    pub async fn node_sql_with_options(
+        &self,
+        req: &Request,
+        options: &RequestOptions,
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        self.send_rpc_to_node(&self.node, &sql_uri("api/sql", options), Some(req))
+            .await
+    }
+    /// Execute a statement that doesn't return rows, such as an `INSERT` or an `UPDATE`.
+    ///
+    /// Returns the number of rows affected and the rowid of the last inserted row.
     pub async fn execute(
         &self,
         query: &str,
-        arguments: Vec<Value>,
-    ) -> Result<Rows, crate::RXQLiteError> {
-        let req = Message::Execute(query.into(), arguments);
+        arguments: impl Into<Parameters>,
+    ) -> Result<ExecuteResult, RXQLiteError> {
+        self.execute_with_options(query, arguments, &RequestOptions::default())
+            .await
+    }
+    /// Like [`RXQLiteClient::execute`], with per request `options`.
+    pub async fn execute_with_options(
+        &self,
+        query: &str,
+        arguments: impl Into<Parameters>,
+        options: &RequestOptions,
+    ) -> Result<ExecuteResult, RXQLiteError> {
+        let req = Message::Execute(query.into(), arguments.into());
         let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
-            .send_rpc_to_leader("api/sql-consistent", Some(&req))
+            .send_rpc_to_leader(&sql_uri("api/sql-consistent", options), Some(&req))
             .await;
         match res {
             Ok(res) => match res.data {
                 Some(res) => match res {
-                    MessageResponse::Rows(rows) => Ok(rows),
-                    MessageResponse::Error(error) => Err(anyhow::anyhow!(error)),
+                    MessageResponse::Executed(result) => Ok(result),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!("unexpected response: {:?}", other))),
                 },
-                _ => Ok(Rows::default()),
+                _ => Ok(ExecuteResult::default()),
             },
-            Err(err) => Err(anyhow::anyhow!(err)),
+            Err(err) => Err(rpc_error(err)),
         }
     }
     pub async fn fetch_all(
         &self,
         query: &str,
-        arguments: Vec<Value>,
-    ) -> Result<Rows, crate::RXQLiteError> {
-        let req = Message::Fetch(query.into(), arguments);
+        arguments: impl Into<Parameters>,
+    ) -> Result<Rows, RXQLiteError> {
+        let req = Message::Fetch(query.into(), arguments.into());
         let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
             .send_rpc_to_leader("api/sql-consistent", Some(&req))
             .await;
@@ -424,19 +474,20 @@ impl RXQLiteClient {
             Ok(res) => match res.data {
                 Some(res) => match res {
                     MessageResponse::Rows(rows) => Ok(rows),
-                    MessageResponse::Error(error) => Err(anyhow::anyhow!(error)),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!("unexpected response: {:?}", other))),
                 },
                 _ => Ok(Rows::default()),
             },
-            Err(err) => Err(anyhow::anyhow!(err)),
+            Err(err) => Err(rpc_error(err)),
         }
     }
     pub async fn fetch_one(
         &self,
         query: &str,
-        arguments: Vec<Value>,
-    ) -> Result<rxqlite_common::Row, crate::RXQLiteError> {
-        let req = Message::FetchOne(query.into(), arguments);
+        arguments: impl Into<Parameters>,
+    ) -> Result<rxqlite_common::Row, RXQLiteError> {
+        let req = Message::FetchOne(query.into(), arguments.into());
         let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
             .send_rpc_to_leader("api/sql-consistent", Some(&req))
             .await;
@@ -447,22 +498,23 @@ impl RXQLiteClient {
                         if rows.len() >= 1 {
                             Ok(rows.remove(0))
                         } else {
-                            Err(anyhow::anyhow!("no row matching query"))
+                            Err(RXQLiteError::new(ErrorKind::RowNotFound, "no row matching query"))
                         }
                     }
-                    MessageResponse::Error(error) => Err(anyhow::anyhow!(error)),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!("unexpected response: {:?}", other))),
                 },
-                _ => Err(anyhow::anyhow!("no row matching query")),
+                _ => Err(RXQLiteError::new(ErrorKind::RowNotFound, "no row matching query")),
             },
-            Err(err) => Err(anyhow::anyhow!(err)),
+            Err(err) => Err(rpc_error(err)),
         }
     }
     pub async fn fetch_optional(
         &self,
         query: &str,
-        arguments: Vec<Value>,
-    ) -> Result<Option<rxqlite_common::Row>, crate::RXQLiteError> {
-        let req = Message::FetchOptional(query.into(), arguments);
+        arguments: impl Into<Parameters>,
+    ) -> Result<Option<rxqlite_common::Row>, RXQLiteError> {
+        let req = Message::FetchOptional(query.into(), arguments.into());
         let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
             .send_rpc_to_leader("api/sql-consistent", Some(&req))
             .await;
@@ -476,11 +528,94 @@ impl RXQLiteClient {
                             Ok(None)
                         }
                     }
-                    MessageResponse::Error(error) => Err(anyhow::anyhow!(error)),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!("unexpected response: {:?}", other))),
                 },
                 _ => Ok(None),
             },
-            Err(err) => Err(anyhow::anyhow!(err)),
+            Err(err) => Err(rpc_error(err)),
+        }
+    }
+
+    /// Like [`RXQLiteClient::fetch_all`], deserializing each row into a `T`:
+    /// structs are matched with the columns by name, tuples by position.
+    pub async fn fetch_as<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        arguments: impl Into<Parameters>,
+    ) -> Result<Vec<T>, RXQLiteError> {
+        let rows = self.fetch_all(query, arguments).await?;
+        Ok(rxqlite_common::from_rows(&rows)?)
+    }
+    pub async fn fetch_one_as<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        arguments: impl Into<Parameters>,
+    ) -> Result<T, RXQLiteError> {
+        let row = self.fetch_one(query, arguments).await?;
+        Ok(rxqlite_common::from_row(&row)?)
+    }
+    pub async fn fetch_optional_as<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        arguments: impl Into<Parameters>,
+    ) -> Result<Option<T>, RXQLiteError> {
+        match self.fetch_optional(query, arguments).await? {
+            Some(row) => Ok(Some(rxqlite_common::from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Execute many statements in a single request, replicated as a single raft log entry.
+    ///
+    /// Returns one result per statement, in order: a failing statement doesn't prevent
+    /// the other ones from being applied.
+    pub async fn execute_batch(
+        &self,
+        statements: Vec<(String, impl Into<Parameters>)>,
+    ) -> Result<Vec<Result<ExecuteResult, RXQLiteError>>, RXQLiteError> {
+        let req = Message::Batch(
+            statements
+                .into_iter()
+                .map(|(sql, arguments)| (sql, arguments.into()))
+                .collect(),
+        );
+        let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
+            .send_rpc_to_leader("api/sql-consistent", Some(&req))
+            .await;
+        match res {
+            Ok(res) => match res.data {
+                Some(res) => match res {
+                    MessageResponse::Batch(responses) => Ok(responses
+                        .into_iter()
+                        .map(|response| match response {
+                            MessageResponse::Executed(result) => Ok(result),
+                            MessageResponse::Error(error) => Err(error),
+                            other => Err(RXQLiteError::other(format!(
+                                "unexpected response: {:?}",
+                                other
+                            ))),
+                        })
+                        .collect()),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!(
+                        "unexpected response to a batch: {:?}",
+                        other
+                    ))),
+                },
+                _ => Err(RXQLiteError::other("no response to batch")),
+            },
+            Err(err) => Err(rpc_error(err)),
+        }
+    }
+
+    /// Start building a transaction: the statements added to the returned builder
+    /// are replicated as a single raft log entry and applied atomically on commit.
+    pub fn transaction(&self) -> TransactionBuilder<'_> {
+        TransactionBuilder {
+            client: self,
+            messages: vec![],
+            options: Default::default(),
         }
     }
 
@@ -497,6 +632,19 @@ impl RXQLiteClient {
             .await
     }
 
+    /// Change the entry of a node in the membership of the cluster: its api and rpc
+    /// addresses, and whether it reads compressed snapshots.
+    ///
+    /// The addresses mustn't be the ones of another node: see
+    /// [`openraft::ChangeMembers::SetNodes`].
+    pub async fn update_node(
+        &self,
+        req: (NodeId, Node),
+    ) -> Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> {
+        self.send_rpc_to_leader("cluster/update-node", Some(&req))
+            .await
+    }
+
     /// Change membership to the specified set of nodes.
     ///
     /// All nodes in `req` have to be already added as learner with [`add_learner`],
@@ -515,7 +663,7 @@ impl RXQLiteClient {
     /// membership config, replication status etc.
     /// See [`RaftMetrics`].
     pub async fn metrics(&self) -> Result<RaftMetrics<NodeId, Node>, typ::RPCError> {
-        self.do_send_rpc_to_leader("cluster/metrics", None::<&()>)
+        self.send_rpc_to_node(&self.leader, "cluster/metrics", None::<&()>)
             .await
     }
 
@@ -525,7 +673,24 @@ impl RXQLiteClient {
     /// membership config, replication status etc.
     /// See [`RaftMetrics`].
     pub async fn node_metrics(&self) -> Result<RaftMetrics<NodeId, Node>, typ::RPCError> {
-        self.do_send_rpc_to_node(&self.node, "cluster/metrics", None::<&()>)
+        self.send_rpc_to_node(&self.node, "cluster/metrics", None::<&()>)
+            .await
+    }
+
+    /// Get the compaction policy of the original node, and where it stands.
+    pub async fn node_compaction_metrics(&self) -> Result<CompactionMetrics, typ::RPCError> {
+        let metrics: CompactionReport = self
+            .send_rpc_to_node(&self.node, "cluster/metrics", None::<&()>)
+            .await?;
+        Ok(metrics.compaction)
+    }
+
+    /// Ask the leader to build a snapshot of its state machine.
+    ///
+    /// The snapshot is built in the background: [`RaftMetrics::snapshot`] tells when it's
+    /// done.
+    pub async fn trigger_snapshot(&self) -> Result<(), RPCError<NodeId, Node, Fatal<NodeId>>> {
+        self.send_rpc_to_node(&self.leader, "cluster/snapshot", Some(&Empty {}))
             .await
     }
 
@@ -541,6 +706,7 @@ impl RXQLiteClient {
         dest_node: &Arc<Mutex<(NodeId, String)>>,
         uri: &str,
         req: Option<&Req>,
+        timeout: Option<Duration>,
     ) -> Result<Resp, RPCError<NodeId, Node, Err>>
     where
         Req: Serialize + 'static,
@@ -561,7 +727,7 @@ impl RXQLiteClient {
             )
         };
 
-        let resp = if let Some(r) = req {
+        let mut request = if let Some(r) = req {
             println!(
                 ">>> client send request to {}: {}",
                 url,
@@ -571,10 +737,18 @@ impl RXQLiteClient {
         } else {
             println!(">>> client send request to {}", url,);
             self.inner.get(url.clone())
+        };
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
         }
-        .send()
-        .await
-        .map_err(|e| RPCError::Network(NetworkError::new(&e)))?;
+        let resp = request.send().await.map_err(|e| {
+            // the request didn't reach the node
+            if e.is_connect() {
+                RPCError::Unreachable(Unreachable::new(&e))
+            } else {
+                RPCError::Network(NetworkError::new(&e))
+            }
+        })?;
 
         let res: Result<Resp, Err> = resp
             .json()
@@ -598,19 +772,107 @@ impl RXQLiteClient {
         &self,
         uri: &str,
         req: Option<&Req>,
+        timeout: Option<Duration>,
+    ) -> Result<Resp, RPCError<NodeId, Node, Err>>
+    where
+        Req: Serialize + 'static,
+        Resp: Serialize + DeserializeOwned,
+        Err: std::error::Error + Serialize + DeserializeOwned,
+    {
+        self.do_send_rpc_to_node(&self.leader, uri, req, timeout).await
+    }
+
+    /// Send RPC to specified node, again after the errors the retry policy allows.
+    async fn send_rpc_to_node<Req, Resp, Err>(
+        &self,
+        dest_node: &Arc<Mutex<(NodeId, String)>>,
+        uri: &str,
+        req: Option<&Req>,
     ) -> Result<Resp, RPCError<NodeId, Node, Err>>
     where
         Req: Serialize + 'static,
         Resp: Serialize + DeserializeOwned,
         Err: std::error::Error + Serialize + DeserializeOwned,
     {
-        self.do_send_rpc_to_node(&self.leader, uri, req).await
+        let mut attempts = Attempts::new(&self.retry_policy);
+        loop {
+            let rpc_err = match self
+                .do_send_rpc_to_node(dest_node, uri, req, attempts.timeout())
+                .await
+            {
+                Ok(x) => return Ok(x),
+                Err(rpc_err) => rpc_err,
+            };
+            let retry = match &rpc_err {
+                RPCError::Unreachable(_) if attempts.retry_on().unreachable => {
+                    attempts.backoff().await
+                }
+                RPCError::Network(_) if attempts.retry_on().network => attempts.backoff().await,
+                _ => false,
+            };
+            if !retry {
+                return Err(rpc_err);
+            }
+        }
+    }
+
+    /// Asks the nodes this client knows of for the leader, learning the members of the
+    /// cluster from the first one that knows it.
+    ///
+    /// Returns whether the leader was found.
+    async fn discover_leader(&self, timeout: Option<Duration>) -> bool {
+        let nodes = self.nodes.lock().unwrap().clone();
+        for node_addr in nodes {
+            let url = format!(
+                "{}://{}/cluster/metrics",
+                if self.use_tls { "https" } else { "http" },
+                node_addr
+            );
+            let mut request = self.inner.get(url);
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+            let metrics: RaftMetrics<NodeId, Node> = match request.send().await {
+                Ok(resp) => match resp.json::<Result<_, openraft::error::Infallible>>().await {
+                    Ok(Ok(metrics)) => metrics,
+                    _ => continue,
+                },
+                Err(_) => continue,
+            };
+            let leader_id = match metrics.current_leader {
+                Some(leader_id) => leader_id,
+                None => continue,
+            };
+            let members = metrics
+                .membership_config
+                .nodes()
+                .map(|(node_id, node)| (*node_id, node.api_addr.clone()))
+                .collect::<Vec<_>>();
+            let leader_addr = match members.iter().find(|(node_id, _)| *node_id == leader_id) {
+                Some((_, leader_addr)) => leader_addr.clone(),
+                None => continue,
+            };
+            {
+                let mut nodes = self.nodes.lock().unwrap();
+                for (_, api_addr) in members {
+                    if !nodes.contains(&api_addr) {
+                        nodes.push(api_addr);
+                    }
+                }
+            }
+            *self.leader.lock().unwrap() = (leader_id, leader_addr);
+            return true;
+        }
+        false
     }
 
     /// Try the best to send a request to the leader.
     ///
     /// If the target node is not a leader, a `ForwardToLeader` error will be
-    /// returned and this client will retry at most 3 times to contact the updated leader.
+    /// returned and this client will send the request again to the updated leader.
+    /// If the target node can't be reached, the client asks the other nodes it knows of
+    /// for the leader, see [`RXQLiteClient::nodes`]. The attempts are bounded by the
+    /// [`RetryPolicy`] of the client.
     async fn send_rpc_to_leader<Req, Resp, Err>(
         &self,
         uri: &str,
@@ -625,43 +887,151 @@ impl RXQLiteClient {
             + TryAsRef<typ::ForwardToLeader>
             + Clone,
     {
-        // Retry at most 3 times to find a valid leader.
-        let mut n_retry = 3;
+        self.send_rpc_to_leader_with_policy(uri, req, &self.retry_policy)
+            .await
+    }
 
+    async fn send_rpc_to_leader_with_policy<Req, Resp, Err>(
+        &self,
+        uri: &str,
+        req: Option<&Req>,
+        retry_policy: &RetryPolicy,
+    ) -> Result<Resp, typ::RPCError<Err>>
+    where
+        Req: Serialize + 'static,
+        Resp: Serialize + DeserializeOwned,
+        Err: std::error::Error
+            + Serialize
+            + DeserializeOwned
+            + TryAsRef<typ::ForwardToLeader>
+            + Clone,
+    {
+        let mut attempts = Attempts::new(retry_policy);
         loop {
-            let res: Result<Resp, typ::RPCError<Err>> = self.do_send_rpc_to_leader(uri, req).await;
+            let res: Result<Resp, typ::RPCError<Err>> =
+                self.do_send_rpc_to_leader(uri, req, attempts.timeout()).await;
 
             let rpc_err = match res {
                 Ok(x) => return Ok(x),
                 Err(rpc_err) => rpc_err,
             };
 
-            if let RPCError::RemoteError(remote_err) = &rpc_err {
-                let raft_err: &typ::RaftError<_> = &remote_err.source;
-
-                if let Some(typ::ForwardToLeader {
-                    leader_id,   //: Some(leader_id),
-                    leader_node, //: Some(leader_node),
-                    ..
-                }) = raft_err.forward_to_leader()
-                {
-                    // Update target to the new leader.
-                    if let (Some(leader_id), Some(leader_node)) = (leader_id, leader_node) {
-                        let mut t = self.leader.lock().unwrap();
-                        let api_addr = leader_node.api_addr.clone();
-                        *t = (*leader_id, api_addr);
+            let retry = match &rpc_err {
+                RPCError::Unreachable(_) if attempts.retry_on().unreachable => {
+                    let unreachable_leader = self.leader.lock().unwrap().clone();
+                    if self.discover_leader(attempts.timeout()).await
+                        && *self.leader.lock().unwrap() != unreachable_leader
+                    {
+                        attempts.redirect()
                     } else {
-                        break Err(rpc_err);
+                        attempts.backoff().await
                     }
-
-                    n_retry -= 1;
-                    if n_retry > 0 {
-                        continue;
+                }
+                RPCError::Network(_) if attempts.retry_on().network => attempts.backoff().await,
+                RPCError::RemoteError(remote_err) => {
+                    let raft_err: &typ::RaftError<_> = &remote_err.source;
+                    match raft_err.forward_to_leader() {
+                        // Update target to the new leader.
+                        Some(typ::ForwardToLeader {
+                            leader_id: Some(leader_id),
+                            leader_node: Some(leader_node),
+                            ..
+                        }) => {
+                            let mut t = self.leader.lock().unwrap();
+                            let api_addr = leader_node.api_addr.clone();
+                            *t = (*leader_id, api_addr);
+                            drop(t);
+                            attempts.redirect()
+                        }
+                        Some(_) if attempts.retry_on().no_leader => attempts.backoff().await,
+                        _ => false,
                     }
                 }
+                _ => false,
+            };
+            if !retry {
+                return Err(rpc_err);
             }
+        }
+    }
+}
+
+/// The error of a request to the sql api that got no response from the leader.
+fn rpc_error<E>(err: typ::RPCError<E>) -> RXQLiteError
+where
+    E: std::error::Error + TryAsRef<typ::ForwardToLeader>,
+{
+    let kind = match &err {
+        RPCError::Timeout(_) | RPCError::Unreachable(_) | RPCError::Network(_) => {
+            ErrorKind::Network
+        }
+        RPCError::RemoteError(_) if err.forward_to_leader().is_some() => ErrorKind::NotLeader,
+        _ => ErrorKind::Other,
+    };
+    RXQLiteError::new(kind, err.to_string())
+}
+
+/// The uri of the sql api at `path` for a request with `options`.
+pub(crate) fn sql_uri(path: &str, options: &RequestOptions) -> String {
+    let query = options.query_string();
+    if query.is_empty() {
+        path.into()
+    } else {
+        format!("{}?{}", path, query)
+    }
+}
 
-            return Err(rpc_err);
+pub struct TransactionBuilder<'a> {
+    client: &'a RXQLiteClient,
+    messages: Vec<Message>,
+    options: RequestOptions,
+}
+
+impl<'a> TransactionBuilder<'a> {
+    /// The options of the request that commits the transaction.
+    pub fn options(mut self, options: RequestOptions) -> Self {
+        self.options = options;
+        self
+    }
+    pub fn execute(mut self, query: &str, arguments: impl Into<Parameters>) -> Self {
+        self.messages.push(Message::Execute(query.into(), arguments.into()));
+        self
+    }
+    pub fn fetch_all(mut self, query: &str, arguments: impl Into<Parameters>) -> Self {
+        self.messages.push(Message::Fetch(query.into(), arguments.into()));
+        self
+    }
+    pub fn fetch_one(mut self, query: &str, arguments: impl Into<Parameters>) -> Self {
+        self.messages.push(Message::FetchOne(query.into(), arguments.into()));
+        self
+    }
+    pub fn fetch_optional(mut self, query: &str, arguments: impl Into<Parameters>) -> Self {
+        self.messages.push(Message::FetchOptional(query.into(), arguments.into()));
+        self
+    }
+    /// Submit the transaction to the cluster.
+    ///
+    /// On success, returns one response per statement, in the order they were added.
+    /// If any statement fails, the whole transaction is rolled back and the error is returned.
+    pub async fn commit(self) -> Result<Vec<MessageResponse>, RXQLiteError> {
+        let req = Message::Transaction(self.messages);
+        let res: Result<typ::ClientWriteResponse, typ::RPCError<typ::ClientWriteError>> = self
+            .client
+            .send_rpc_to_leader(&sql_uri("api/sql-consistent", &self.options), Some(&req))
+            .await;
+        match res {
+            Ok(res) => match res.data {
+                Some(res) => match res {
+                    MessageResponse::Transaction(responses) => Ok(responses),
+                    MessageResponse::Error(error) => Err(error),
+                    other => Err(RXQLiteError::other(format!(
+                        "unexpected response to a transaction: {:?}",
+                        other
+                    ))),
+                },
+                _ => Err(RXQLiteError::other("no response to transaction")),
+            },
+            Err(err) => Err(rpc_error(err)),
         }
     }
 }
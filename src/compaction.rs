@@ -0,0 +1,150 @@
+//! When a node builds snapshots of its state machine, and how many of the logs they include
+//! it keeps.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use openraft::{Config, LogId, SnapshotPolicy};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::app::App;
+use crate::NodeId;
+
+/// How often a node checks the thresholds openraft doesn't check itself.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a node builds snapshots and purges logs. Each node is given its own every time it
+/// starts. A threshold of 0 is disabled, and a node builds a snapshot when any other is
+/// reached, or when `cluster/snapshot` is called.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompactionPolicy {
+    /// Build a snapshot once this many logs were applied since the last one.
+    pub snapshot_logs_since_last: u64,
+    /// Build a snapshot once the logs appended since the last one take this many bytes.
+    pub snapshot_log_bytes: u64,
+    /// Build a snapshot once this many seconds passed since the last one, if logs were
+    /// applied since.
+    pub snapshot_interval_secs: u64,
+    /// How many of the logs included in the last snapshot are kept, for the followers
+    /// lagging behind to catch up without the snapshot.
+    pub max_in_snapshot_log_to_keep: u64,
+    /// The fewest logs purged at once.
+    pub purge_batch_size: u64,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        // the defaults of openraft
+        Self {
+            snapshot_logs_since_last: 5000,
+            snapshot_log_bytes: 0,
+            snapshot_interval_secs: 0,
+            max_in_snapshot_log_to_keep: 1000,
+            purge_batch_size: 1,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    /// Sets the part of the policy openraft applies itself.
+    pub(crate) fn configure(&self, config: &mut Config) {
+        config.snapshot_policy = match self.snapshot_logs_since_last {
+            0 => SnapshotPolicy::Never,
+            logs => SnapshotPolicy::LogsSinceLast(logs),
+        };
+        config.max_in_snapshot_log_to_keep = self.max_in_snapshot_log_to_keep;
+        config.purge_batch_size = self.purge_batch_size;
+    }
+}
+
+/// The compaction policy of a node and where it stands, reported by `cluster/metrics`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CompactionMetrics {
+    pub policy: CompactionPolicy,
+    /// The bytes of the logs appended since the last snapshot, or since the node started.
+    pub log_bytes_since_snapshot: u64,
+    /// The seconds since the last snapshot, or since the node started.
+    pub secs_since_snapshot: u64,
+}
+
+/// Where a node stands against its compaction policy.
+pub struct Compaction {
+    pub policy: CompactionPolicy,
+    /// The bytes of the logs appended since the last snapshot, counted by the log store.
+    log_bytes: Arc<AtomicU64>,
+    /// The last snapshot, and when this node learnt of it.
+    last_snapshot: Mutex<(Option<LogId<NodeId>>, Instant)>,
+}
+
+impl Compaction {
+    pub(crate) fn new(policy: CompactionPolicy, log_bytes: Arc<AtomicU64>) -> Self {
+        Self {
+            policy,
+            log_bytes,
+            last_snapshot: Mutex::new((None, Instant::now())),
+        }
+    }
+
+    pub fn metrics(&self) -> CompactionMetrics {
+        let at = self.last_snapshot.lock().unwrap().1;
+        CompactionMetrics {
+            policy: self.policy,
+            log_bytes_since_snapshot: self.log_bytes.load(Ordering::Relaxed),
+            secs_since_snapshot: at.elapsed().as_secs(),
+        }
+    }
+
+    /// Starts counting again from `snapshot` when it's a new one.
+    fn observe(&self, snapshot: Option<LogId<NodeId>>) {
+        let mut last_snapshot = self.last_snapshot.lock().unwrap();
+        if last_snapshot.0 != snapshot {
+            *last_snapshot = (snapshot, Instant::now());
+            self.log_bytes.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether a snapshot should be built, `last_applied` being the last log applied.
+    fn is_due(&self, last_applied: Option<LogId<NodeId>>) -> bool {
+        let (snapshot, at) = *self.last_snapshot.lock().unwrap();
+        if last_applied.map(|log_id| log_id.index) <= snapshot.map(|log_id| log_id.index) {
+            return false;
+        }
+        let bytes = self.policy.snapshot_log_bytes;
+        let interval = self.policy.snapshot_interval_secs;
+        (bytes > 0 && self.log_bytes.load(Ordering::Relaxed) >= bytes)
+            || (interval > 0 && at.elapsed() >= Duration::from_secs(interval))
+    }
+}
+
+/// Builds the snapshots the byte and time thresholds of the policy of the node call for,
+/// until raft shuts down.
+pub(crate) async fn run(app: Arc<App>) {
+    let mut ticks = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let (running, snapshot, last_applied) = {
+            let metrics = app.raft.metrics();
+            let metrics = metrics.borrow();
+            (
+                metrics.running_state.is_ok(),
+                metrics.snapshot,
+                metrics.last_applied,
+            )
+        };
+        if !running {
+            return;
+        }
+        app.compaction.observe(snapshot);
+        if app.compaction.is_due(last_applied) {
+            // ignored while a snapshot is being built, tried again on the next tick when it
+            // fails: the loop only ends once raft stopped running
+            if let Err(err) = app.raft.trigger().snapshot().await {
+                tracing::warn!("failed to trigger a snapshot: {}", err);
+                continue;
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use rxqlite_common::Value;
+use sqlx_core::arguments::Arguments;
+use sqlx_core::encode::{Encode, IsNull};
+use sqlx_core::types::Type;
+
+use super::RXQLite;
+
+/// The positional parameters of a statement, sent along with it to the cluster.
+#[derive(Default, Debug, Clone)]
+pub struct RXQLiteArguments {
+    pub(crate) values: Vec<Value>,
+}
+
+impl<'q> Arguments<'q> for RXQLiteArguments {
+    type Database = RXQLite;
+
+    fn reserve(&mut self, len: usize, _size_hint: usize) {
+        self.values.reserve(len);
+    }
+
+    fn add<T>(&mut self, value: T)
+    where
+        T: Encode<'q, RXQLite> + Type<RXQLite>,
+    {
+        if let IsNull::Yes = value.encode(&mut self.values) {
+            self.values.push(Value::Null);
+        }
+    }
+}
+
+sqlx_core::impl_into_arguments_for_arguments!(RXQLiteArguments);
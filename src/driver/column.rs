@@ -0,0 +1,40 @@
+use sqlx_core::column::Column;
+
+use super::{RXQLite, RXQLiteTypeInfo};
+
+#[derive(Debug, Clone)]
+pub struct RXQLiteColumn {
+    pub(crate) name: String,
+    pub(crate) ordinal: usize,
+    pub(crate) type_info: RXQLiteTypeInfo,
+}
+
+impl Column for RXQLiteColumn {
+    type Database = RXQLite;
+
+    fn ordinal(&self) -> usize {
+        self.ordinal
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn type_info(&self) -> &RXQLiteTypeInfo {
+        &self.type_info
+    }
+}
+
+impl RXQLiteColumn {
+    pub(crate) fn from_header(columns: &[rxqlite_common::Column]) -> Vec<Self> {
+        columns
+            .iter()
+            .enumerate()
+            .map(|(ordinal, column)| Self {
+                name: column.name.clone(),
+                ordinal,
+                type_info: RXQLiteTypeInfo::of_decl_type(column.decl_type.as_deref()),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use sqlx_core::connection::Connection;
+use sqlx_core::database::HasStatement;
+use sqlx_core::describe::Describe;
+use sqlx_core::executor::{Execute, Executor};
+use sqlx_core::transaction::Transaction;
+use sqlx_core::Either;
+
+use super::{database_error, RXQLite, RXQLiteColumn, RXQLiteQueryResult, RXQLiteRow};
+use super::{RXQLiteStatement, RXQLiteTypeInfo};
+use crate::client::RXQLiteClient;
+
+impl std::fmt::Debug for RXQLiteClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RXQLiteClient")
+            .field("leader", &self.leader)
+            .field("node", &self.node)
+            .field("use_tls", &self.use_tls)
+            .finish()
+    }
+}
+
+impl Connection for RXQLiteClient {
+    type Database = RXQLite;
+
+    type Options = crate::ConnectOptions;
+
+    fn close(self) -> BoxFuture<'static, Result<(), sqlx_core::Error>> {
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn close_hard(self) -> BoxFuture<'static, Result<(), sqlx_core::Error>> {
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn ping(&mut self) -> BoxFuture<'_, Result<(), sqlx_core::Error>> {
+        Box::pin(async move {
+            self.metrics()
+                .await
+                .map(|_| ())
+                .map_err(|err| {
+                    database_error(crate::RXQLiteError::new(
+                        crate::ErrorKind::Network,
+                        err.to_string(),
+                    ))
+                })
+        })
+    }
+
+    fn begin(&mut self) -> BoxFuture<'_, Result<Transaction<'_, RXQLite>, sqlx_core::Error>>
+    where
+        Self: Sized,
+    {
+        Transaction::begin(self)
+    }
+
+    fn shrink_buffers(&mut self) {}
+
+    fn flush(&mut self) -> BoxFuture<'_, Result<(), sqlx_core::Error>> {
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn should_flush(&self) -> bool {
+        false
+    }
+}
+
+impl<'c> Executor<'c> for &'c mut RXQLiteClient {
+    type Database = RXQLite;
+
+    /// Statements are sent with [`RXQLiteClient::fetch_all`], the leader replicating the
+    /// ones that write: they yield their rows, such as the ones of a `RETURNING` clause,
+    /// then their query result.
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        mut query: E,
+    ) -> BoxStream<'e, Result<Either<RXQLiteQueryResult, RXQLiteRow>, sqlx_core::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, RXQLite>,
+    {
+        let sql = query.sql();
+        let arguments = query
+            .take_arguments()
+            .map(|arguments| arguments.values)
+            .unwrap_or_default();
+        let results = async move {
+            let rows = RXQLiteClient::fetch_all(self, sql, arguments)
+                .await
+                .map_err(database_error)?;
+            let result = rows.executed().map(Into::into).unwrap_or_default();
+            let columns = Arc::new(RXQLiteColumn::from_header(rows.columns()));
+            let mut results: Vec<_> = rows
+                .into_iter()
+                .map(|row| Either::Right(RXQLiteRow::new(row, columns.clone())))
+                .collect();
+            results.push(Either::Left(result));
+            Ok::<_, sqlx_core::Error>(results)
+        };
+        Box::pin(
+            futures::stream::once(results)
+                .map_ok(|results| futures::stream::iter(results.into_iter().map(Ok)))
+                .try_flatten(),
+        )
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<RXQLiteRow>, sqlx_core::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, RXQLite>,
+    {
+        let mut results = self.fetch_many(query);
+        Box::pin(async move {
+            while let Some(result) = results.try_next().await? {
+                if let Either::Right(row) = result {
+                    return Ok(Some(row));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    /// Statements are prepared by the nodes: the returned statement carries no
+    /// column information.
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        _parameters: &'e [RXQLiteTypeInfo],
+    ) -> BoxFuture<'e, Result<<RXQLite as HasStatement<'q>>::Statement, sqlx_core::Error>>
+    where
+        'c: 'e,
+    {
+        Box::pin(async move {
+            Ok(RXQLiteStatement {
+                sql: sql.into(),
+                columns: Default::default(),
+            })
+        })
+    }
+
+    fn describe<'e, 'q: 'e>(
+        self,
+        _sql: &'q str,
+    ) -> BoxFuture<'e, Result<Describe<RXQLite>, sqlx_core::Error>>
+    where
+        'c: 'e,
+    {
+        Box::pin(async move {
+            Err(sqlx_core::Error::Protocol(
+                "rxqlite doesn't support describing statements".into(),
+            ))
+        })
+    }
+}
@@ -0,0 +1,139 @@
+//! A [`sqlx`] driver for a rxqlite cluster, so that code written against `sqlx` can
+//! target rxqlite: `sqlx::query("...").fetch_all(&pool)` with a `sqlx::Pool<RXQLite>`.
+//!
+//! The connection is [`RXQLiteClient`](crate::client::RXQLiteClient) and its options are
+//! [`ConnectOptions`](crate::ConnectOptions), parsed from `rxqlite://` urls.
+//!
+//! Every statement is a request to the cluster, so there are no interactive transactions:
+//! use [`RXQLiteClient::transaction`](crate::client::RXQLiteClient::transaction) instead.
+
+use sqlx_core::database::{Database, HasArguments, HasStatement, HasValueRef};
+
+mod arguments;
+mod column;
+mod connection;
+mod options;
+mod query_result;
+mod row;
+mod statement;
+mod transaction;
+mod type_info;
+mod types;
+mod value;
+
+pub use arguments::RXQLiteArguments;
+pub use column::RXQLiteColumn;
+pub use options::URL_SCHEME;
+pub use query_result::RXQLiteQueryResult;
+pub use row::RXQLiteRow;
+pub use statement::RXQLiteStatement;
+pub use transaction::RXQLiteTransactionManager;
+pub use type_info::RXQLiteTypeInfo;
+pub use value::{RXQLiteValue, RXQLiteValueRef};
+
+/// rxqlite database driver.
+#[derive(Debug)]
+pub struct RXQLite;
+
+impl Database for RXQLite {
+    type Connection = crate::client::RXQLiteClient;
+
+    type TransactionManager = RXQLiteTransactionManager;
+
+    type Row = RXQLiteRow;
+
+    type QueryResult = RXQLiteQueryResult;
+
+    type Column = RXQLiteColumn;
+
+    type TypeInfo = RXQLiteTypeInfo;
+
+    type Value = RXQLiteValue;
+
+    const NAME: &'static str = "RXQLite";
+
+    const URL_SCHEMES: &'static [&'static str] = &[URL_SCHEME];
+}
+
+impl<'r> HasValueRef<'r> for RXQLite {
+    type Database = RXQLite;
+
+    type ValueRef = RXQLiteValueRef<'r>;
+}
+
+impl<'q> HasArguments<'q> for RXQLite {
+    type Database = RXQLite;
+
+    type Arguments = RXQLiteArguments;
+
+    type ArgumentBuffer = Vec<rxqlite_common::Value>;
+}
+
+impl<'q> HasStatement<'q> for RXQLite {
+    type Database = RXQLite;
+
+    type Statement = RXQLiteStatement<'q>;
+}
+
+pub(crate) fn database_error(err: crate::RXQLiteError) -> sqlx_core::Error {
+    sqlx_core::Error::Database(Box::new(RXQLiteDatabaseError(err)))
+}
+
+/// An error returned by the cluster.
+#[derive(Debug)]
+pub struct RXQLiteDatabaseError(crate::RXQLiteError);
+
+impl RXQLiteDatabaseError {
+    /// The error, with its kind and the result code sqlite reported.
+    pub fn error(&self) -> &crate::RXQLiteError {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RXQLiteDatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for RXQLiteDatabaseError {}
+
+impl sqlx_core::error::DatabaseError for RXQLiteDatabaseError {
+    fn message(&self) -> &str {
+        &self.0.message
+    }
+
+    fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.0.code.map(|code| code.to_string().into())
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> sqlx_core::error::ErrorKind {
+        use sqlx_core::error::ErrorKind;
+        match self.0.kind {
+            crate::ErrorKind::Constraint(crate::ConstraintKind::Unique)
+            | crate::ErrorKind::Constraint(crate::ConstraintKind::PrimaryKey) => {
+                ErrorKind::UniqueViolation
+            }
+            crate::ErrorKind::Constraint(crate::ConstraintKind::ForeignKey) => {
+                ErrorKind::ForeignKeyViolation
+            }
+            crate::ErrorKind::Constraint(crate::ConstraintKind::NotNull) => {
+                ErrorKind::NotNullViolation
+            }
+            crate::ErrorKind::Constraint(crate::ConstraintKind::Check) => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use log::LevelFilter;
+use sqlx_core::Url;
+
+use crate::client::RXQLiteClient;
+use crate::ConnectOptions;
+use rxqlite_common::RSQliteClientTlsConfig;
+
+pub const URL_SCHEME: &str = "rxqlite";
+
+const DEFAULT_PORT: u16 = 21001;
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, sqlx_core::Error> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(sqlx_core::Error::Configuration(
+            format!("invalid value {:?} for {}", value, key).into(),
+        )),
+    }
+}
+
+/// `rxqlite://host:port?leader_id=1&tls=yes&accept_invalid_certificates=yes&cert_path=ca.pem&seed=host2:port2`
+///
+/// `leader_id` defaults to 1, the node the cluster is initialized from.
+/// `cert_path` may be repeated and implies `tls`.
+/// `seed` may be repeated, see [`ConnectOptions::seeds`].
+impl FromStr for ConnectOptions {
+    type Err = sqlx_core::Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let url: Url = url
+            .parse()
+            .map_err(|err| sqlx_core::Error::Configuration(Box::new(err)))?;
+        <Self as sqlx_core::connection::ConnectOptions>::from_url(&url)
+    }
+}
+
+impl sqlx_core::connection::ConnectOptions for ConnectOptions {
+    type Connection = RXQLiteClient;
+
+    fn from_url(url: &Url) -> Result<Self, sqlx_core::Error> {
+        if url.scheme() != URL_SCHEME {
+            return Err(sqlx_core::Error::Configuration(
+                format!(
+                    "unsupported url scheme {}, expected {}",
+                    url.scheme(),
+                    URL_SCHEME
+                )
+                .into(),
+            ));
+        }
+        let leader_host = url
+            .host_str()
+            .ok_or_else(|| sqlx_core::Error::Configuration("missing host in url".into()))?
+            .to_string();
+        let mut options = ConnectOptions {
+            leader_id: 1,
+            leader_host,
+            leader_port: url.port().unwrap_or(DEFAULT_PORT),
+            tls_config: None,
+            seeds: vec![],
+            retry_policy: Default::default(),
+        };
+        let mut use_tls = false;
+        let mut tls_config = RSQliteClientTlsConfig::default();
+        for (key, value) in url.query_pairs() {
+            match &*key {
+                "leader_id" => {
+                    options.leader_id = value.parse().map_err(|_| {
+                        sqlx_core::Error::Configuration(
+                            format!("invalid leader_id {:?}", value).into(),
+                        )
+                    })?;
+                }
+                "tls" => {
+                    use_tls = parse_bool(&key, &value)?;
+                }
+                "accept_invalid_certificates" => {
+                    tls_config.accept_invalid_certificates = parse_bool(&key, &value)?;
+                }
+                "cert_path" => {
+                    use_tls = true;
+                    tls_config.cert_paths.push(value.into_owned());
+                }
+                "seed" => {
+                    options.seeds.push(value.into_owned());
+                }
+                _ => {
+                    return Err(sqlx_core::Error::Configuration(
+                        format!("unknown url parameter {}", key).into(),
+                    ));
+                }
+            }
+        }
+        if use_tls || tls_config.accept_invalid_certificates {
+            options.tls_config = Some(tls_config);
+        }
+        Ok(options)
+    }
+
+    fn to_url_lossy(&self) -> Url {
+        let mut url = Url::parse(&format!(
+            "{}://{}:{}",
+            URL_SCHEME, self.leader_host, self.leader_port
+        ))
+        .expect("BUG: generated un-parseable URL");
+        url.query_pairs_mut()
+            .append_pair("leader_id", &self.leader_id.to_string());
+        for seed in self.seeds.iter() {
+            url.query_pairs_mut().append_pair("seed", seed);
+        }
+        if let Some(tls_config) = self.tls_config.as_ref() {
+            url.query_pairs_mut().append_pair("tls", "yes");
+            if tls_config.accept_invalid_certificates {
+                url.query_pairs_mut()
+                    .append_pair("accept_invalid_certificates", "yes");
+            }
+            for cert_path in tls_config.cert_paths.iter() {
+                url.query_pairs_mut().append_pair("cert_path", cert_path);
+            }
+        }
+        url
+    }
+
+    fn connect(&self) -> BoxFuture<'_, Result<RXQLiteClient, sqlx_core::Error>> {
+        Box::pin(async move { Ok(RXQLiteClient::with_options(self)) })
+    }
+
+    // statements are logged by the nodes
+    fn log_statements(self, _level: LevelFilter) -> Self {
+        self
+    }
+
+    fn log_slow_statements(self, _level: LevelFilter, _duration: Duration) -> Self {
+        self
+    }
+}
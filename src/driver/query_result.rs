@@ -0,0 +1,35 @@
+use rxqlite_common::ExecuteResult;
+
+#[derive(Debug, Default)]
+pub struct RXQLiteQueryResult {
+    pub(super) rows_affected: u64,
+    pub(super) last_insert_rowid: i64,
+}
+
+impl RXQLiteQueryResult {
+    pub fn rows_affected(&self) -> u64 {
+        self.rows_affected
+    }
+
+    pub fn last_insert_rowid(&self) -> i64 {
+        self.last_insert_rowid
+    }
+}
+
+impl From<ExecuteResult> for RXQLiteQueryResult {
+    fn from(result: ExecuteResult) -> Self {
+        Self {
+            rows_affected: result.rows_affected,
+            last_insert_rowid: result.last_insert_rowid,
+        }
+    }
+}
+
+impl Extend<RXQLiteQueryResult> for RXQLiteQueryResult {
+    fn extend<T: IntoIterator<Item = RXQLiteQueryResult>>(&mut self, iter: T) {
+        for elem in iter {
+            self.rows_affected += elem.rows_affected;
+            self.last_insert_rowid = elem.last_insert_rowid;
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use sqlx_core::column::ColumnIndex;
+use sqlx_core::row::Row;
+
+use super::{RXQLite, RXQLiteColumn, RXQLiteValue, RXQLiteValueRef};
+
+pub struct RXQLiteRow {
+    pub(crate) values: Vec<RXQLiteValue>,
+    pub(crate) columns: Arc<Vec<RXQLiteColumn>>,
+}
+
+impl Row for RXQLiteRow {
+    type Database = RXQLite;
+
+    fn columns(&self) -> &[RXQLiteColumn] {
+        &self.columns
+    }
+
+    fn try_get_raw<I>(&self, index: I) -> Result<RXQLiteValueRef<'_>, sqlx_core::Error>
+    where
+        I: ColumnIndex<Self>,
+    {
+        let index = index.index(self)?;
+        Ok(RXQLiteValueRef::new(&self.values[index]))
+    }
+}
+
+impl RXQLiteRow {
+    pub(crate) fn new(row: rxqlite_common::Row, columns: Arc<Vec<RXQLiteColumn>>) -> Self {
+        Self {
+            values: row.inner.into_iter().map(RXQLiteValue::new).collect(),
+            columns,
+        }
+    }
+}
+
+impl ColumnIndex<RXQLiteRow> for &'_ str {
+    fn index(&self, row: &RXQLiteRow) -> Result<usize, sqlx_core::Error> {
+        row.columns
+            .iter()
+            .position(|column| column.name == *self)
+            .ok_or_else(|| sqlx_core::Error::ColumnNotFound((*self).into()))
+    }
+}
+
+sqlx_core::impl_column_index_for_row!(RXQLiteRow);
@@ -0,0 +1,53 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use sqlx_core::column::ColumnIndex;
+use sqlx_core::statement::Statement;
+use sqlx_core::Either;
+
+use super::{RXQLite, RXQLiteArguments, RXQLiteColumn, RXQLiteTypeInfo};
+
+/// Statements are prepared by the nodes when they are executed:
+/// the client side statement only holds the sql text.
+#[derive(Debug, Clone)]
+pub struct RXQLiteStatement<'q> {
+    pub(crate) sql: Cow<'q, str>,
+    pub(crate) columns: Arc<Vec<RXQLiteColumn>>,
+}
+
+impl<'q> Statement<'q> for RXQLiteStatement<'q> {
+    type Database = RXQLite;
+
+    fn to_owned(&self) -> RXQLiteStatement<'static> {
+        RXQLiteStatement {
+            sql: Cow::Owned(self.sql.clone().into_owned()),
+            columns: Arc::clone(&self.columns),
+        }
+    }
+
+    fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    fn parameters(&self) -> Option<Either<&[RXQLiteTypeInfo], usize>> {
+        None
+    }
+
+    fn columns(&self) -> &[RXQLiteColumn] {
+        &self.columns
+    }
+
+    sqlx_core::impl_statement_query!(RXQLiteArguments);
+}
+
+impl ColumnIndex<RXQLiteStatement<'_>> for &'_ str {
+    fn index(&self, statement: &RXQLiteStatement<'_>) -> Result<usize, sqlx_core::Error> {
+        statement
+            .columns
+            .iter()
+            .position(|column| column.name == *self)
+            .ok_or_else(|| sqlx_core::Error::ColumnNotFound((*self).into()))
+    }
+}
+
+sqlx_core::impl_column_index_for_statement!(RXQLiteStatement);
@@ -0,0 +1,35 @@
+use futures::future::BoxFuture;
+use sqlx_core::transaction::TransactionManager;
+
+use super::RXQLite;
+use crate::client::RXQLiteClient;
+
+/// Each statement is replicated on its own, a transaction can't span several requests.
+///
+/// Statements that must be applied atomically are sent together with
+/// [`RXQLiteClient::transaction`].
+pub struct RXQLiteTransactionManager;
+
+fn unsupported() -> sqlx_core::Error {
+    sqlx_core::Error::Configuration(
+        "rxqlite doesn't support interactive transactions, use RXQLiteClient::transaction".into(),
+    )
+}
+
+impl TransactionManager for RXQLiteTransactionManager {
+    type Database = RXQLite;
+
+    fn begin(_conn: &mut RXQLiteClient) -> BoxFuture<'_, Result<(), sqlx_core::Error>> {
+        Box::pin(async { Err(unsupported()) })
+    }
+
+    fn commit(_conn: &mut RXQLiteClient) -> BoxFuture<'_, Result<(), sqlx_core::Error>> {
+        Box::pin(async { Err(unsupported()) })
+    }
+
+    fn rollback(_conn: &mut RXQLiteClient) -> BoxFuture<'_, Result<(), sqlx_core::Error>> {
+        Box::pin(async { Err(unsupported()) })
+    }
+
+    fn start_rollback(_conn: &mut RXQLiteClient) {}
+}
@@ -0,0 +1,75 @@
+use std::fmt::{self, Display, Formatter};
+
+use rxqlite_common::Value;
+use sqlx_core::type_info::TypeInfo;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum DataType {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Text,
+    Blob,
+    Numeric,
+    Datetime,
+}
+
+/// Type information for a rxqlite value: the sqlite storage class of the value, or the
+/// declared type of a column.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RXQLiteTypeInfo(pub(crate) DataType);
+
+impl Display for RXQLiteTypeInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl TypeInfo for RXQLiteTypeInfo {
+    fn is_null(&self) -> bool {
+        matches!(self.0, DataType::Null)
+    }
+
+    // the names sqlx's sqlite driver uses
+    fn name(&self) -> &str {
+        match self.0 {
+            DataType::Null => "NULL",
+            DataType::Bool => "BOOLEAN",
+            DataType::Int => "INTEGER",
+            DataType::Float => "REAL",
+            DataType::Text => "TEXT",
+            DataType::Blob => "BLOB",
+            DataType::Numeric => "NUMERIC",
+            DataType::Datetime => "DATETIME",
+        }
+    }
+}
+
+impl RXQLiteTypeInfo {
+    pub(crate) fn of_value(value: &Value) -> Self {
+        Self(match value {
+            Value::Null => DataType::Null,
+            Value::Bool(_) => DataType::Bool,
+            Value::Int(_) => DataType::Int,
+            Value::F32(_) | Value::F64(_) => DataType::Float,
+            Value::String(_) => DataType::Text,
+            Value::DateTime(_) => DataType::Datetime,
+            Value::Blob(_) => DataType::Blob,
+        })
+    }
+
+    /// The type of a column as reported by the cluster in the result header.
+    pub(crate) fn of_decl_type(decl_type: Option<&str>) -> Self {
+        Self(match decl_type {
+            Some("BOOLEAN") => DataType::Bool,
+            Some("INTEGER") => DataType::Int,
+            Some("REAL") => DataType::Float,
+            Some("TEXT") | Some("DATE") | Some("TIME") => DataType::Text,
+            Some("BLOB") => DataType::Blob,
+            Some("NUMERIC") => DataType::Numeric,
+            Some("DATETIME") => DataType::Datetime,
+            _ => DataType::Null,
+        })
+    }
+}
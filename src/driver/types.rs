@@ -0,0 +1,133 @@
+//! Conversions between rust types and rxqlite values.
+//!
+//! | Rust type                | rxqlite value      |
+//! |--------------------------|--------------------|
+//! | `bool`                   | `Bool`, `Int`      |
+//! | `i16`, `i32`, `i64`      | `Int`              |
+//! | `f32`, `f64`             | `F32`, `F64`       |
+//! | `&str`, `String`         | `String`           |
+//! | `&[u8]`, `Vec<u8>`       | `Blob`             |
+//! | `chrono::DateTime<Utc>`  | `DateTime`         |
+//! | `chrono::NaiveDateTime`  | `DateTime` (UTC)   |
+//!
+//! Decoding goes through [`TryFromValue`], so a value is only decoded into a type
+//! it converts to without loss.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rxqlite_common::{TryFromValue, Value};
+use sqlx_core::decode::Decode;
+use sqlx_core::encode::{Encode, IsNull};
+use sqlx_core::error::BoxDynError;
+use sqlx_core::types::Type;
+
+use super::type_info::DataType;
+use super::{RXQLite, RXQLiteTypeInfo, RXQLiteValueRef};
+
+fn decode<T: TryFromValue>(value: RXQLiteValueRef<'_>) -> Result<T, BoxDynError> {
+    Ok(T::try_from_value(value.value())?)
+}
+
+macro_rules! impl_type {
+    ($ty:ty, $data_type:ident, [$($compatible:ident),*], |$value:ident| $to_value:expr) => {
+        impl Type<RXQLite> for $ty {
+            fn type_info() -> RXQLiteTypeInfo {
+                RXQLiteTypeInfo(DataType::$data_type)
+            }
+
+            fn compatible(ty: &RXQLiteTypeInfo) -> bool {
+                matches!(ty.0, $(DataType::$compatible)|*)
+            }
+        }
+
+        impl<'q> Encode<'q, RXQLite> for $ty {
+            fn encode_by_ref(&self, buf: &mut Vec<Value>) -> IsNull {
+                let $value = self;
+                buf.push($to_value);
+                IsNull::No
+            }
+        }
+
+        impl<'r> Decode<'r, RXQLite> for $ty {
+            fn decode(value: RXQLiteValueRef<'r>) -> Result<Self, BoxDynError> {
+                decode(value)
+            }
+        }
+    };
+}
+
+impl_type!(bool, Bool, [Bool, Int], |v| Value::Bool(*v));
+impl_type!(i16, Int, [Int], |v| Value::Int(*v as i64));
+impl_type!(i32, Int, [Int], |v| Value::Int(*v as i64));
+impl_type!(i64, Int, [Int], |v| Value::Int(*v));
+impl_type!(f32, Float, [Float, Int], |v| Value::F32(*v));
+impl_type!(f64, Float, [Float, Int], |v| Value::F64(*v));
+impl_type!(String, Text, [Text], |v| Value::String(v.clone()));
+impl_type!(Vec<u8>, Blob, [Blob], |v| Value::Blob(v.clone()));
+impl_type!(DateTime<Utc>, Datetime, [Datetime, Text], |v| {
+    Value::DateTime(*v)
+});
+impl_type!(NaiveDateTime, Datetime, [Datetime, Text], |v| {
+    Value::DateTime(v.and_utc())
+});
+
+impl Type<RXQLite> for str {
+    fn type_info() -> RXQLiteTypeInfo {
+        <String as Type<RXQLite>>::type_info()
+    }
+
+    fn compatible(ty: &RXQLiteTypeInfo) -> bool {
+        <String as Type<RXQLite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, RXQLite> for &'q str {
+    fn encode_by_ref(&self, buf: &mut Vec<Value>) -> IsNull {
+        buf.push(Value::String((*self).to_string()));
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, RXQLite> for &'r str {
+    fn decode(value: RXQLiteValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value() {
+            Value::String(s) => Ok(s),
+            other => Err(rxqlite_common::ValueError::Mismatch {
+                expected: "String",
+                found: other.type_name(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl Type<RXQLite> for [u8] {
+    fn type_info() -> RXQLiteTypeInfo {
+        <Vec<u8> as Type<RXQLite>>::type_info()
+    }
+
+    fn compatible(ty: &RXQLiteTypeInfo) -> bool {
+        <Vec<u8> as Type<RXQLite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, RXQLite> for &'q [u8] {
+    fn encode_by_ref(&self, buf: &mut Vec<Value>) -> IsNull {
+        buf.push(Value::Blob(self.to_vec()));
+        IsNull::No
+    }
+}
+
+impl<'r> Decode<'r, RXQLite> for &'r [u8] {
+    fn decode(value: RXQLiteValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value() {
+            Value::Blob(b) => Ok(b),
+            other => Err(rxqlite_common::ValueError::Mismatch {
+                expected: "Blob",
+                found: other.type_name(),
+            }
+            .into()),
+        }
+    }
+}
+
+sqlx_core::impl_encode_for_option!(RXQLite);
@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+
+use rxqlite_common::Value as RXQLiteCommonValue;
+use sqlx_core::value::{Value, ValueRef};
+
+use super::{RXQLite, RXQLiteTypeInfo};
+
+#[derive(Debug, Clone)]
+pub struct RXQLiteValue {
+    pub(crate) value: RXQLiteCommonValue,
+    pub(crate) type_info: RXQLiteTypeInfo,
+}
+
+impl RXQLiteValue {
+    pub(crate) fn new(value: RXQLiteCommonValue) -> Self {
+        let type_info = RXQLiteTypeInfo::of_value(&value);
+        Self { value, type_info }
+    }
+}
+
+pub struct RXQLiteValueRef<'r>(&'r RXQLiteValue);
+
+impl<'r> RXQLiteValueRef<'r> {
+    pub(crate) fn new(value: &'r RXQLiteValue) -> Self {
+        Self(value)
+    }
+
+    pub(crate) fn value(&self) -> &'r RXQLiteCommonValue {
+        &self.0.value
+    }
+}
+
+impl Value for RXQLiteValue {
+    type Database = RXQLite;
+
+    fn as_ref(&self) -> RXQLiteValueRef<'_> {
+        RXQLiteValueRef(self)
+    }
+
+    fn type_info(&self) -> Cow<'_, RXQLiteTypeInfo> {
+        Cow::Borrowed(&self.type_info)
+    }
+
+    fn is_null(&self) -> bool {
+        self.value.is_null()
+    }
+}
+
+impl<'r> ValueRef<'r> for RXQLiteValueRef<'r> {
+    type Database = RXQLite;
+
+    fn to_owned(&self) -> RXQLiteValue {
+        self.0.clone()
+    }
+
+    fn type_info(&self) -> Cow<'_, RXQLiteTypeInfo> {
+        Cow::Borrowed(&self.0.type_info)
+    }
+
+    fn is_null(&self) -> bool {
+        self.0.value.is_null()
+    }
+}
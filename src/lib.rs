@@ -5,7 +5,6 @@
 #![deny(warnings)]
 
 use std::fmt::Display;
-use std::io::Cursor;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -30,7 +29,9 @@ use crate::store::Response;
 
 pub mod app;
 pub mod client;
+pub mod compaction;
 pub mod network;
+pub mod retry;
 pub mod sqlite_store;
 
 pub mod cipher;
@@ -39,6 +40,9 @@ pub use cipher::NoEncrypt;
 
 pub mod notifications;
 
+pub mod driver;
+pub use driver::RXQLite;
+
 pub use rxqlite_common::RSQliteClientTlsConfig;
 use rxqlite_common::RSQliteNodeTlsConfig;
 use sqlite_store as store;
@@ -79,6 +83,10 @@ pub struct Node {
     pub rpc_addr: String,
     pub api_addr: String,
     //pub tls_config: Option<RSQliteNodeTlsConfig>,
+    /// Whether the node reads compressed snapshots. Nodes set it on their own entry of the
+    /// membership, and the leader sends uncompressed snapshots until every node has.
+    #[serde(default)]
+    pub compressed_snapshots: bool,
 }
 /*
 impl Node {
@@ -107,7 +115,7 @@ impl Display for Node {
     }
 }
 
-pub type SnapshotData = Cursor<Vec<u8>>;
+pub type SnapshotData = tokio::fs::File;
 
 openraft::declare_raft_types!(
     pub TypeConfig:
@@ -164,18 +172,358 @@ where
 }
 */
 
+/// How writes are replicated. The mode of the leader decides, so every node of a cluster
+/// should be given the same one.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// Every node runs the statements of a write.
+    #[default]
+    Statements,
+    /// The leader runs the statements of a write and replicates the rows they change,
+    /// see [`rxqlite_sqlx_common::capture_changeset`].
+    Changeset,
+}
+
+impl std::str::FromStr for WriteMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "statements" => Ok(Self::Statements),
+            "changeset" => Ok(Self::Changeset),
+            _ => Err(format!(
+                "unknown write mode {}: expected statements or changeset",
+                s
+            )),
+        }
+    }
+}
+
+/// How a node makes sure it still is the leader before it answers a `sql-consistent` read.
+/// Unlike the [`WriteMode`], each node is given its own every time it starts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Every read waits for a quorum of the cluster to acknowledge the leader.
+    #[default]
+    Quorum,
+    /// Once a quorum acknowledged it, the leader answers reads on its own until its lease
+    /// expires: the followers don't elect another leader before their election timeout.
+    Lease,
+}
+
+impl std::str::FromStr for ReadMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quorum" => Ok(Self::Quorum),
+            "lease" => Ok(Self::Lease),
+            _ => Err(format!(
+                "unknown read mode {}: expected quorum or lease",
+                s
+            )),
+        }
+    }
+}
+
+/// How a node compresses the snapshots it builds. Snapshots tell how they were compressed,
+/// so that each node is given its own every time it starts, and reads the snapshots of the
+/// others whatever their compression.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotCompression {
+    #[default]
+    None,
+    /// zstd, at the given level.
+    Zstd(i32),
+    /// lz4, faster than zstd but compressing less.
+    Lz4,
+}
+
+impl std::str::FromStr for SnapshotCompression {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            _ => match s.strip_prefix("zstd:").map(str::parse::<i32>) {
+                Some(Ok(level)) if zstd::compression_level_range().contains(&level) => {
+                    Ok(Self::Zstd(level))
+                }
+                _ => Err(format!(
+                    "unknown snapshot compression {}: expected none, lz4, zstd or zstd:<level>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// The timing of raft on a node, in milliseconds. The election timeouts of the nodes of a
+/// cluster should be the same, and well above the time a heartbeat takes between them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RaftTiming {
+    /// How often the leader sends heartbeats to the followers.
+    pub heartbeat_interval: u64,
+    /// How long a follower waits without hearing from the leader before campaigning, at
+    /// least.
+    pub election_timeout_min: u64,
+    /// How long a follower waits without hearing from the leader before campaigning, at
+    /// most.
+    pub election_timeout_max: u64,
+    /// How long the leader answers reads on its own in [`ReadMode::Lease`] once a quorum
+    /// acknowledged it, 90% of `election_timeout_min` less `max_clock_drift` by default.
+    pub lease_duration: Option<u64>,
+    /// How much sooner than on the leader an election timeout may elapse on a follower,
+    /// the clocks of the nodes drifting apart: the lease ends that much before it.
+    pub max_clock_drift: u64,
+}
+
+impl Default for RaftTiming {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: 250,
+            election_timeout_min: 299,
+            election_timeout_max: 300,
+            lease_duration: None,
+            max_clock_drift: 10,
+        }
+    }
+}
+
+impl RaftTiming {
+    /// The lease of the leader, refused unless it ends `max_clock_drift` before the
+    /// followers which acknowledged the leader campaign.
+    pub fn lease_duration(&self) -> anyhow::Result<std::time::Duration> {
+        if self.max_clock_drift >= self.election_timeout_min {
+            return Err(anyhow::anyhow!(
+                "max_clock_drift ({}ms) must be shorter than election_timeout_min ({}ms)",
+                self.max_clock_drift,
+                self.election_timeout_min
+            ));
+        }
+        let max_lease = self.election_timeout_min - self.max_clock_drift;
+        let lease = match self.lease_duration {
+            Some(lease) if lease > max_lease => {
+                return Err(anyhow::anyhow!(
+                    "lease_duration ({}ms) must not exceed election_timeout_min less \
+                     max_clock_drift ({}ms)",
+                    lease,
+                    max_lease
+                ))
+            }
+            Some(lease) => lease,
+            None => (self.election_timeout_min * 9 / 10).saturating_sub(self.max_clock_drift),
+        };
+        Ok(std::time::Duration::from_millis(lease))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct InstanceParams {
     pub http_addr: String,
     pub rpc_addr: String,
     pub notifications_addr: String,
     tls_config: Option<RSQliteNodeTlsConfig>,
+    #[serde(default)]
+    pub write_mode: WriteMode,
+    // the key of the tls configuration encrypts the database and the logs unless set
+    #[serde(default)]
+    pub no_database_encryption: bool,
+}
+
+/// Writes the parameters of a node, replacing the ones it had.
+async fn save_instance_params(
+    base_dir: &Path,
+    instance_params: &InstanceParams,
+) -> anyhow::Result<()> {
+    let instance_params_json = serde_json::to_string(instance_params)?;
+    let path = base_dir.join("instance_params.json");
+    let part_path = base_dir.join("instance_params.json.part");
+    tokio::fs::write(&part_path, instance_params_json.as_bytes()).await?;
+    tokio::fs::rename(&part_path, &path).await?;
+    Ok(())
+}
+
+/// The private key of a tls configuration.
+fn read_private_key(key_path: &str) -> anyhow::Result<Vec<u8>> {
+    rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(&mut File::open(key_path)?))
+        .filter_map(|x| x.ok())
+        .next()
+        .map(|private_key| private_key.secret_pkcs8_der().to_vec())
+        .ok_or_else(|| anyhow::anyhow!("No valid certificate found in {}", key_path))
+}
+
+/// Refuses the tls configuration a node restarts with when the one it was initialized with
+/// can't be replaced by it: tls can't be turned on, and the key which encrypts the database
+/// can't change.
+fn check_restart_tls_config(
+    instance_params: &InstanceParams,
+    tls_config: &RSQliteNodeTlsConfig,
+) -> anyhow::Result<()> {
+    let initial = match instance_params.tls_config.as_ref() {
+        Some(initial) => initial,
+        None => {
+            return Err(anyhow::anyhow!(
+                "the node was initialized without tls, it can't restart with it"
+            ))
+        }
+    };
+    if instance_params.no_database_encryption || !cfg!(feature = "sqlcipher") {
+        return Ok(());
+    }
+    // a key file that moved can't be compared, opening the database tells then
+    if let Ok(initial_key) = read_private_key(&initial.key_path) {
+        if read_private_key(&tls_config.key_path)? != initial_key {
+            return Err(anyhow::anyhow!(
+                "{} holds another key than {}, which encrypts the database of the node",
+                tls_config.key_path,
+                initial.key_path
+            ));
+        }
+    }
+    Ok(())
 }
 
+/// Once the node knows the leader, changes the addresses the membership of the cluster
+/// holds for it to the ones it listens on, for the other nodes to reach it after it
+/// restarted with new ones, and tells it reads compressed snapshots, which the nodes
+/// added as learners don't tell.
+///
+/// The node doesn't take the addresses another member has in the membership it knows,
+/// which would let two quorums elect a leader each.
+async fn update_node_addresses(app: Arc<App>, tls_config: Option<RSQliteNodeTlsConfig>) {
+    let node = Node {
+        rpc_addr: app.rpc_addr.clone(),
+        api_addr: app.api_addr.clone(),
+        compressed_snapshots: true,
+    };
+    let mut metrics = app.raft.metrics();
+    loop {
+        let stale = {
+            let metrics = metrics.borrow_and_update();
+            if metrics.running_state.is_err() {
+                return;
+            }
+            let membership = metrics.membership_config.membership();
+            match membership.get_node(&app.id) {
+                Some(known) if *known == node => return,
+                Some(_) => {
+                    let members = membership
+                        .nodes()
+                        .filter(|(node_id, _)| **node_id != app.id)
+                        .map(|(node_id, member)| (*node_id, member.clone()))
+                        .collect::<Vec<_>>();
+                    Some((metrics.current_leader, members))
+                }
+                // the membership isn't loaded yet, or the node will be added with the
+                // addresses it has
+                None => None,
+            }
+        };
+        let (leader, members) = match stale {
+            Some(stale) => stale,
+            None => {
+                if metrics.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        if let Some((node_id, _)) = members.iter().find(|(_, member)| {
+            member.api_addr == node.api_addr || member.rpc_addr == node.rpc_addr
+        }) {
+            tracing::error!(
+                "node {} has the addresses of {} in the membership, it isn't updated",
+                node_id,
+                node
+            );
+            return;
+        }
+        tracing::info!("updating the addresses of node {} to {}", app.id, node);
+        let res = if leader == Some(app.id) {
+            let nodes = BTreeMap::from([(app.id, node.clone())]);
+            app.raft
+                .change_membership(openraft::ChangeMembers::SetNodes(nodes), false)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        } else {
+            match members.first() {
+                Some((node_id, member)) => {
+                    let client =
+                        client::RXQLiteClientBuilder::new(*node_id, member.api_addr.clone())
+                            .use_tls(tls_config.is_some())
+                            .accept_invalid_certificates(
+                                tls_config
+                                    .as_ref()
+                                    .map(|tls_config| tls_config.accept_invalid_certificates)
+                                    .unwrap_or(false),
+                            )
+                            .build();
+                    client.nodes.lock().unwrap().extend(
+                        members
+                            .iter()
+                            .skip(1)
+                            .map(|(_, member)| member.api_addr.clone()),
+                    );
+                    client
+                        .update_node((app.id, node.clone()))
+                        .await
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                }
+                None => Err("the node is the only member of the cluster".to_string()),
+            }
+        };
+        match res {
+            // the membership the change committed is then awaited
+            Ok(()) => {
+                if metrics.changed().await.is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                tracing::warn!("failed to update the addresses of node {}: {}", app.id, err);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Requests a membership change again while another one isn't committed yet, such as a
+/// learner telling it reads compressed snapshots.
+async fn retry_membership_change<F, Fut>(
+    mut change: F,
+) -> Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<
+        Output = Result<typ::ClientWriteResponse, typ::RaftError<typ::ClientWriteError>>,
+    >,
+{
+    loop {
+        match change().await {
+            Err(openraft::error::RaftError::APIError(
+                openraft::error::ClientWriteError::ChangeMembershipError(
+                    openraft::error::ChangeMembershipError::InProgress(_),
+                ),
+            )) => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+            res => return res,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn init_rxqlite<P>(
     node_id: NodeId,
     base_dir: P,
     instance_params: InstanceParams,
+    read_mode: ReadMode,
+    forward_reads: bool,
+    snapshot_compression: SnapshotCompression,
+    raft_timing: RaftTiming,
+    compaction_policy: CompactionPolicy,
 ) -> anyhow::Result<(Arc<App>, tokio::task::JoinHandle<()>)>
 where
     P: AsRef<Path>,
@@ -183,7 +531,11 @@ where
     let (_key, encrypt_data): (Option<String>, Option<Arc<Box<dyn EncryptData>>>) = {
         #[cfg(feature = "sqlcipher")]
         {
-            if let Some(tls_config) = instance_params.tls_config.as_ref() {
+            let tls_config = instance_params
+                .tls_config
+                .as_ref()
+                .filter(|_| !instance_params.no_database_encryption);
+            if let Some(tls_config) = tls_config {
                 let private_key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(
                     &mut File::open(&tls_config.key_path)?,
                 ))
@@ -217,19 +569,27 @@ where
     };
     let rocksdb_dir = base_dir.as_ref().join("rocksdb");
     let sqlite_path = base_dir.as_ref().join("sqlite.db");
+    let snapshot_dir = base_dir.as_ref().join("snapshots");
 
     // Create a configuration for the raft instance.
-    let config = Config {
-        heartbeat_interval: 250,
-        election_timeout_min: 299,
+    let mut config = Config {
+        heartbeat_interval: raft_timing.heartbeat_interval,
+        election_timeout_min: raft_timing.election_timeout_min,
+        election_timeout_max: raft_timing.election_timeout_max,
         ..Default::default()
     };
+    compaction_policy.configure(&mut config);
 
-    let config = Arc::new(config.validate().unwrap());
+    let config = Arc::new(config.validate()?);
+
+    // a follower acknowledging the leader doesn't campaign before election_timeout_min
+    let lease_duration = raft_timing.lease_duration()?;
 
     let (log_store, state_machine_store) = new_storage(
         &rocksdb_dir,
         &sqlite_path,
+        &snapshot_dir,
+        snapshot_compression,
         #[cfg(feature = "sqlcipher")]
         _key,
         encrypt_data,
@@ -237,6 +597,30 @@ where
     .await?;
 
     let sqlite_and_path = state_machine_store.data.sqlite_and_path.clone();
+    let compaction = compaction::Compaction::new(compaction_policy, log_store.log_bytes());
+
+    let read_forwarder = if forward_reads {
+        // the leader may wait for its own reads to catch up before answering
+        let mut client = reqwest::Client::builder()
+            .connect_timeout(api::FORWARD_CONNECT_TIMEOUT)
+            .timeout(api::FORWARD_CONNECT_TIMEOUT + api::READ_WAIT_TIMEOUT);
+        if let Some(tls_config) = instance_params.tls_config.as_ref() {
+            // the nodes of a cluster share the certificate
+            let pem = std::fs::read(&tls_config.cert_path)?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)?;
+            client = client.use_rustls_tls();
+            for cert in certs {
+                client = client.add_root_certificate(cert);
+            }
+            client = client.danger_accept_invalid_certs(tls_config.accept_invalid_certificates);
+        }
+        Some(app::ReadForwarder {
+            client: client.build()?,
+            use_tls: instance_params.tls_config.is_some(),
+        })
+    } else {
+        None
+    };
 
     // Create the network layer that will connect and communicate the raft instances and
     // will be used in conjunction with the store created above.
@@ -263,7 +647,16 @@ where
         //key_values: kvs,
         sqlite_and_path,
         config,
+        write_mode: instance_params.write_mode,
+        write_lock: Default::default(),
+        leader_contact: tokio::sync::watch::channel(None).0,
+        read_mode,
+        lease_duration,
+        leader_lease: tokio::sync::watch::channel(None).0,
+        read_forwarder,
+        compaction,
     });
+    tokio::spawn(compaction::run(app.clone()));
     let echo_service = Arc::new(network::raft::Raft::new(app.clone()));
 
     let mut server_builder = toy_rpc_ha421::Server::builder();
@@ -366,14 +759,21 @@ where
     let execute_consistent_query = warp::post()
         .and(warp::path!("api" / "sql-consistent"))
         .and(warp::body::json())
+        .and(warp::query::<RequestOptions>())
+        .and(warp::header::optional::<NodeId>(api::FORWARDED_BY_HEADER))
         .and(with_app(app.clone()))
-        .and_then(|arg0: Message, arg1: Arc<App>| api::sql_consistent(arg0, arg1));
+        .and_then(
+            |arg0: Message, arg1: RequestOptions, arg2: Option<NodeId>, arg3: Arc<App>| {
+                api::sql_consistent(arg0, arg1, arg2, arg3)
+            },
+        );
 
     let execute_query = warp::post()
         .and(warp::path!("api" / "sql"))
         .and(warp::body::json())
+        .and(warp::query::<RequestOptions>())
         .and(with_app(app.clone()))
-        .and_then(|arg0: Message, arg1: Arc<App>| api::sql(arg0, arg1));
+        .and_then(|arg0: Message, arg1: RequestOptions, arg2: Arc<App>| api::sql(arg0, arg1, arg2));
 
     let management_add_learner = warp::post()
         .and(warp::path!("cluster" / "add-learner"))
@@ -387,6 +787,12 @@ where
         .and(with_app(app.clone()))
         .and_then(management::change_membership);
 
+    let management_update_node = warp::post()
+        .and(warp::path!("cluster" / "update-node"))
+        .and(warp::body::json())
+        .and(with_app(app.clone()))
+        .and_then(management::update_node);
+
     let management_metrics = warp::get()
         .and(warp::path!("cluster" / "metrics"))
         .and(with_app(app.clone()))
@@ -402,6 +808,7 @@ where
         .or(execute_consistent_query)
         .or(management_add_learner)
         .or(management_change_membership)
+        .or(management_update_node)
         //.or(management_init)
         .or(management_metrics)
         .or(management_snapshot);
@@ -497,7 +904,13 @@ pub async fn init_example_raft_node<P>(
     notifications_addr: Option<String>,
     members: Vec<(NodeId, String, String)>,
     tls_config: Option<RSQliteNodeTlsConfig>,
-    _no_database_encryption: bool,
+    write_mode: WriteMode,
+    read_mode: ReadMode,
+    forward_reads: bool,
+    snapshot_compression: SnapshotCompression,
+    raft_timing: RaftTiming,
+    compaction_policy: CompactionPolicy,
+    no_database_encryption: bool,
 ) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
@@ -525,18 +938,27 @@ where
         http_addr: http_addr.clone(),
         rpc_addr: rpc_addr.clone(),
         notifications_addr: notifications_addr.clone(),
-        tls_config,
+        tls_config: tls_config.clone(),
+        write_mode,
+        no_database_encryption,
     };
 
-    let instance_params_json = serde_json::to_string(&instance_params)?;
-
-    tokio::fs::write(
-        base_dir.as_ref().join("instance_params.json"),
-        instance_params_json.as_bytes(),
-    )
-    .await?;
-
-    let (app, handle) = init_rxqlite(node_id, base_dir, instance_params).await?;
+    save_instance_params(base_dir.as_ref(), &instance_params).await?;
+
+    let (app, handle) =
+        init_rxqlite(
+            node_id,
+            base_dir,
+            instance_params,
+            read_mode,
+            forward_reads,
+            snapshot_compression,
+            raft_timing,
+            compaction_policy,
+        )
+        .await?;
+    // the members are added as learners which don't read compressed snapshots yet
+    tokio::spawn(update_node_addresses(app.clone(), tls_config));
 
     if leader {
         let mut nodes = BTreeMap::new();
@@ -544,6 +966,7 @@ where
             api_addr: http_addr,
             rpc_addr: rpc_addr,
             //tls_config:tls_config.clone(),
+            compressed_snapshots: true,
         };
         nodes.insert(app.id, node);
         app.raft.initialize(nodes).await?;
@@ -557,6 +980,8 @@ where
                     rpc_addr,
                     api_addr,
                     //tls_config: None,
+                    // the node tells once it runs
+                    compressed_snapshots: false,
                 };
                 tracing::debug!(
                     "{}({}):adding learner : {}/{}",
@@ -565,7 +990,8 @@ where
                     node_id_,
                     node
                 );
-                app.raft.add_learner(node_id_, node, true).await?;
+                retry_membership_change(|| app.raft.add_learner(node_id_, node.clone(), true))
+                    .await?;
                 tracing::debug!("{}({}):learner added: {}", file!(), line!(), node_id_);
             }
             /*
@@ -592,7 +1018,8 @@ where
             */
             tracing::debug!("{}({}):changing membership", file!(), line!());
             member_ship.insert(app.id);
-            app.raft.change_membership(member_ship, false).await?;
+            retry_membership_change(|| app.raft.change_membership(member_ship.clone(), false))
+                .await?;
 
             tracing::debug!("{}({}):membership changed", file!(), line!());
         }
@@ -608,23 +1035,61 @@ where
     Ok(())
 }
 
+/// Restarts the node initialized in `base_dir`. The addresses and the tls configuration
+/// given replace the ones it had, the membership of the cluster being updated with the new
+/// addresses once the node knows the leader.
 pub async fn start_example_raft_node<P>(
     node_id: NodeId,
     base_dir: P,
-    _http_addr: Option<String>,
-    _rpc_addr: Option<String>,
-    _notifications_addr: Option<String>,
-    _tls_config: Option<RSQliteNodeTlsConfig>,
-    _no_database_encryption: bool,
+    http_addr: Option<String>,
+    rpc_addr: Option<String>,
+    notifications_addr: Option<String>,
+    tls_config: Option<RSQliteNodeTlsConfig>,
+    read_mode: ReadMode,
+    forward_reads: bool,
+    snapshot_compression: SnapshotCompression,
+    raft_timing: RaftTiming,
+    compaction_policy: CompactionPolicy,
 ) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
     let tls_instance_params_json =
         tokio::fs::read_to_string(base_dir.as_ref().join("instance_params.json")).await?;
-    let instance_params: InstanceParams = serde_json::from_str(&tls_instance_params_json)?;
-
-    let (_, handle) = init_rxqlite(node_id, base_dir, instance_params).await?;
+    let mut instance_params: InstanceParams = serde_json::from_str(&tls_instance_params_json)?;
+    if let Some(tls_config) = tls_config {
+        check_restart_tls_config(&instance_params, &tls_config)?;
+        instance_params.tls_config = Some(tls_config);
+    }
+    for (addr, new_addr) in [
+        (&mut instance_params.http_addr, http_addr),
+        (&mut instance_params.rpc_addr, rpc_addr),
+        (&mut instance_params.notifications_addr, notifications_addr),
+    ] {
+        if let Some(new_addr) = new_addr {
+            if *addr != new_addr {
+                tracing::info!("node {} moves from {} to {}", node_id, addr, new_addr);
+                *addr = new_addr;
+            }
+        }
+    }
+    save_instance_params(base_dir.as_ref(), &instance_params).await?;
+    let tls_config = instance_params.tls_config.clone();
+
+    let (app, handle) =
+        init_rxqlite(
+            node_id,
+            base_dir,
+            instance_params,
+            read_mode,
+            forward_reads,
+            snapshot_compression,
+            raft_timing,
+            compaction_policy,
+        )
+        .await?;
+    // the node may also have restarted after a crash which left the membership behind
+    tokio::spawn(update_node_addresses(app, tls_config));
 
     tokio::select! {
       _ = handle => {
@@ -638,7 +1103,10 @@ where
     Ok(())
 }
 
-pub use rxqlite_common::{Message, MessageResponse, Value};
+pub use rxqlite_common::{
+    ConstraintKind, ErrorKind, ExecuteResult, Message, MessageResponse, Parameters, RXQLiteError,
+    RequestId, RequestIds, RequestOptions, Value,
+};
 /*
 #[derive(Debug,Default,Clone,Copy,PartialEq,Eq)]
 pub enum Scheme {
@@ -654,10 +1122,12 @@ pub struct ConnectOptions {
     pub leader_host: String,
     pub leader_port: u16,
     pub tls_config: Option<RSQliteClientTlsConfig>,
+    /// The `host:port` api addresses of other nodes of the cluster, asked for the leader
+    /// when the node the client sends requests to can't be reached.
+    pub seeds: Vec<String>,
+    pub retry_policy: RetryPolicy,
 }
 
-pub type RXQLiteError = anyhow::Error;
-
 impl ConnectOptions {
     pub async fn connect(&self) -> Result<client::RXQLiteClient, RXQLiteError> {
         Ok(client::RXQLiteClient::with_options(self))
@@ -666,6 +1136,10 @@ impl ConnectOptions {
 
 pub use client::RXQLiteClient as Connection;
 
+pub use retry::{RetryOn, RetryPolicy};
+
+pub use compaction::{CompactionMetrics, CompactionPolicy};
+
 pub use rxqlite_common::FromValueRef;
 
 #[cfg(test)]
@@ -1,75 +1,322 @@
 use std::sync::Arc;
+use std::time::Duration;
 
+use openraft::ServerState;
+use tokio::time::Instant;
 use warp::reply;
 
 use crate::app::App;
+use crate::app::LeaderLease;
+use crate::app::ReadForwarder;
 use crate::Node;
+use crate::NodeId;
 use crate::TypeConfig;
 use openraft::LeaderId;
 use openraft::LogId;
-use rxqlite_common::{Message,MessageResponse};
+use crate::ReadMode;
+use crate::WriteMode;
+use rxqlite_common::{ErrorKind,Message,MessageResponse,RXQLiteError,RequestOptions};
+use rxqlite_sqlx_common::Capture;
+
+/// The header of a read a follower forwards to the leader, holding the id of the follower.
+/// The leader doesn't forward it again.
+pub const FORWARDED_BY_HEADER: &str = "x-rxqlite-forwarded-by";
+
+/// How long a read waits for the node to catch up with the freshness it asks for.
+pub(crate) const READ_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a follower waits to connect to the leader it forwards a read to.
+pub(crate) const FORWARD_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A response computed by this node, outside of raft.
+fn local_response(response_message: MessageResponse) -> warp::reply::Json {
+    let client_write_response = openraft::raft::ClientWriteResponse::<TypeConfig> {
+        log_id: LogId {
+            leader_id: LeaderId {
+                term: u64::MAX,
+                node_id: u64::MAX,
+            },
+            index: u64::MAX,
+        },
+        data: Some(response_message),
+        membership: None,
+    };
+    let res = Result::<
+        openraft::raft::ClientWriteResponse<TypeConfig>,
+        openraft::error::RaftError<u64, openraft::error::ClientWriteError<u64, Node>>,
+    >::Ok(client_write_response);
+    reply::json(&res)
+}
+
+fn check_is_leader_error_response(
+    err: openraft::error::RaftError<u64, openraft::error::CheckIsLeaderError<u64, Node>>,
+) -> warp::reply::Json {
+    let err = match err {
+        openraft::error::RaftError::APIError(
+            openraft::error::CheckIsLeaderError::ForwardToLeader(forward_to_leader),
+        ) => openraft::error::RaftError::APIError(
+            openraft::error::ClientWriteError::ForwardToLeader(forward_to_leader),
+        ),
+        openraft::error::RaftError::APIError(
+            openraft::error::CheckIsLeaderError::QuorumNotEnough(err),
+        ) => {
+            return local_response(MessageResponse::Error(RXQLiteError::new(
+                ErrorKind::NotLeader,
+                format!("{}", err),
+            )))
+        }
+        openraft::error::RaftError::Fatal(fatal) => openraft::error::RaftError::Fatal(fatal),
+    };
+    let res = Result::<
+        openraft::raft::ClientWriteResponse<TypeConfig>,
+        openraft::error::RaftError<u64, openraft::error::ClientWriteError<u64, Node>>,
+    >::Err(err);
+    reply::json(&res)
+}
+
+async fn write(message: Message, options: RequestOptions, app: &App) -> warp::reply::Json {
+    // a changeset must be applied before the next one is captured
+    let _write_lock = match app.write_mode {
+        WriteMode::Statements => None,
+        WriteMode::Changeset => Some(app.write_lock.lock().await),
+    };
+    if app.write_mode == WriteMode::Changeset {
+        // captured on top of every committed write
+        if let Err(err) = app.raft.ensure_linearizable().await {
+            return check_is_leader_error_response(err);
+        }
+        // running the write again to capture it would answer its outcome on top of itself
+        if let Some(request_id) = options.id() {
+            let recorded = {
+                let sqlite_and_path = app.sqlite_and_path.read().await;
+                rxqlite_sqlx_common::recorded_response(&sqlite_and_path, &request_id).await
+            };
+            match recorded {
+                Ok(Some(response_message)) => return local_response(response_message),
+                Ok(None) => {}
+                Err(err) => return local_response(MessageResponse::Error(err)),
+            }
+        }
+        let capture = {
+            let sqlite_and_path = app.sqlite_and_path.read().await;
+            rxqlite_sqlx_common::capture_changeset(&sqlite_and_path, &message).await
+        };
+        match capture {
+            Capture::Changeset(changeset) => {
+                let res: Result<openraft::raft::ClientWriteResponse<TypeConfig>, _> =
+                    app.raft.client_write(identified(changeset, &options)).await;
+                return reply::json(&res);
+            }
+            Capture::Failed(response_message) => return local_response(response_message),
+            Capture::Statements => {}
+        }
+    }
+    // every node runs the statements again
+    if !options.allow_non_deterministic {
+        let check = {
+            let sqlite_and_path = app.sqlite_and_path.read().await;
+            rxqlite_sqlx_common::check_deterministic_on(&sqlite_and_path, &message).await
+        };
+        if let Err(err) = check {
+            return local_response(MessageResponse::Error(RXQLiteError::invalid_request(err)));
+        }
+    }
+    let res: Result<openraft::raft::ClientWriteResponse<TypeConfig>, _> =
+        app.raft.client_write(identified(message, &options)).await;
+    reply::json(&res)
+}
+
+/// The message to replicate for a write, carrying its request id when the client sent one.
+fn identified(message: Message, options: &RequestOptions) -> Message {
+    match options.id() {
+        Some(request_id) => Message::WithRequestId {
+            request_id,
+            message: Box::new(message),
+        },
+        None => message,
+    }
+}
+
+async fn wait_for_applied(app: &App, index: u64, deadline: Instant) -> Result<(), String> {
+    app.raft
+        .wait(Some(deadline.saturating_duration_since(Instant::now())))
+        .applied_index_at_least(Some(index), "waiting to serve a read")
+        .await
+        .map(|_| ())
+        .map_err(|err| {
+            format!(
+                "node {} hasn't applied log index {}: {}",
+                app.id, index, err
+            )
+        })
+}
+
+/// Waits for the database of this node to be as fresh as a read asks for in `options`.
+async fn wait_for_freshness(app: &App, options: &RequestOptions) -> Result<(), String> {
+    let deadline = Instant::now() + READ_WAIT_TIMEOUT;
+    if let Some(index) = options.min_applied_index {
+        wait_for_applied(app, index, deadline).await?;
+    }
+    let max_staleness_ms = match options.max_staleness_ms {
+        Some(max_staleness_ms) => max_staleness_ms,
+        None => return Ok(()),
+    };
+    let metrics = app.raft.metrics().borrow().clone();
+    if metrics.state == ServerState::Leader {
+        if metrics
+            .millis_since_quorum_ack
+            .is_some_and(|millis| millis <= max_staleness_ms)
+        {
+            return Ok(());
+        }
+        return app
+            .raft
+            .ensure_linearizable()
+            .await
+            .map(|_| ())
+            .map_err(|err| format!("{}", err));
+    }
+    let max_staleness = Duration::from_millis(max_staleness_ms);
+    let mut leader_contacts = app.leader_contact.subscribe();
+    let leader_commit = loop {
+        let leader_contact = *leader_contacts.borrow_and_update();
+        if let Some(leader_contact) = leader_contact {
+            if leader_contact.received_at.elapsed() <= max_staleness {
+                break leader_contact.leader_commit;
+            }
+        }
+        match tokio::time::timeout_at(deadline, leader_contacts.changed()).await {
+            Ok(Ok(())) => {}
+            _ => {
+                return Err(format!(
+                    "node {} hasn't heard from the leader in the last {}ms",
+                    app.id, max_staleness_ms
+                ))
+            }
+        }
+    };
+    match leader_commit {
+        Some(index) => wait_for_applied(app, index, deadline).await,
+        None => Ok(()),
+    }
+}
+
+/// The term this node is the leader of, according to its metrics.
+fn leader_term(app: &App) -> Option<u64> {
+    let metrics = app.raft.metrics();
+    let metrics = metrics.borrow();
+    (metrics.state == ServerState::Leader).then_some(metrics.current_term)
+}
+
+/// Makes sure this node still is the leader before it answers a linearizable read, checking
+/// with a quorum unless it holds a lease in [`ReadMode::Lease`].
+async fn ensure_linearizable_read(app: &App) -> bool {
+    if app.read_mode == ReadMode::Quorum {
+        return app.raft.ensure_linearizable().await.is_ok();
+    }
+    let term = leader_term(app);
+    if let (Some(term), Some(lease)) = (term, *app.leader_lease.borrow()) {
+        // the writes acknowledged by this node are applied to its database, and no other
+        // node can commit any until the lease expires
+        if lease.term == term && Instant::now() < lease.expires_at {
+            return true;
+        }
+    }
+    // the followers acknowledge heartbeats sent after this instant
+    let started_at = Instant::now();
+    if app.raft.ensure_linearizable().await.is_err() {
+        return false;
+    }
+    if let Some(term) = term.filter(|term| leader_term(app) == Some(*term)) {
+        let lease = LeaderLease {
+            term,
+            expires_at: started_at + app.lease_duration,
+        };
+        app.leader_lease.send_if_modified(|current| match current {
+            Some(current) if current.term == term && current.expires_at >= lease.expires_at => {
+                false
+            }
+            _ => {
+                *current = Some(lease);
+                true
+            }
+        });
+    }
+    true
+}
+
+/// Sends a `sql-consistent` read to the leader, returning its answer.
+async fn forward_read(
+    forwarder: &ReadForwarder,
+    node_id: NodeId,
+    leader_node: &Node,
+    message: &Message,
+    options: &RequestOptions,
+) -> Result<serde_json::Value, reqwest::Error> {
+    let url = format!(
+        "{}://{}/{}",
+        if forwarder.use_tls { "https" } else { "http" },
+        leader_node.api_addr,
+        crate::client::sql_uri("api/sql-consistent", options)
+    );
+    forwarder
+        .client
+        .post(url)
+        .header(FORWARDED_BY_HEADER, node_id)
+        .json(message)
+        .send()
+        .await?
+        .json()
+        .await
+}
 
 pub async fn sql_consistent_or_fast(
     message: Message,
+    options: RequestOptions,
+    forwarded_by: Option<NodeId>,
     app: Arc<App>,
     consistent: bool,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
-    let sql = message.sql();
-
-    let is_write = rxqlite_sqlx_common::is_query_write(sql);
+    match message {
+        Message::Changeset { .. } => {
+            return Ok(local_response(MessageResponse::Error(
+                RXQLiteError::invalid_request("changesets are created by the nodes of the cluster"),
+            )))
+        }
+        Message::WithRequestId { .. } => {
+            return Ok(local_response(MessageResponse::Error(
+                RXQLiteError::invalid_request(
+                    "identify writes with the client_id and sequence options",
+                ),
+            )))
+        }
+        _ => {}
+    }
+    let is_write = {
+        let sqlite_and_path = app.sqlite_and_path.read().await;
+        rxqlite_sqlx_common::is_message_write_on(&sqlite_and_path, &message).await
+    };
     if let Err(err) = &is_write {
-      let response_message = MessageResponse::Error(format!("{}",err));
-      let client_write_response = openraft::raft::ClientWriteResponse::<TypeConfig> {
-            log_id: LogId {
-                leader_id: LeaderId {
-                    term: u64::MAX,
-                    node_id: u64::MAX,
-                },
-                index: u64::MAX,
-            },
-            data: Some(response_message),
-            membership: None,
-        };
-        let res = Result::<
-            openraft::raft::ClientWriteResponse<TypeConfig>,
-            openraft::error::RaftError<u64, openraft::error::ClientWriteError<u64, Node>>,
-        >::Ok(client_write_response);
-        return Ok(reply::json(&res));
+      return Ok(local_response(MessageResponse::Error(RXQLiteError::new(ErrorKind::Sql,format!("{}",err)))));
     }
     let is_write=is_write.unwrap();
     if is_write {
-        let res: Result<openraft::raft::ClientWriteResponse<TypeConfig>, _> =
-            app.raft.client_write(message).await;
-        Ok(reply::json(&res))
+        Ok(write(message, options, &app).await)
     } else {
         let do_it_locally = if consistent {
-            if let Ok(_read_log_id) = app.raft.ensure_linearizable().await {
-                true
-            } else {
-                false
-            }
+            ensure_linearizable_read(&app).await
+        } else if let Err(err) = wait_for_freshness(&app, &options).await {
+            return Ok(local_response(MessageResponse::Error(RXQLiteError::new(
+                ErrorKind::NotCaughtUp,
+                err,
+            ))));
         } else {
             true
         };
         if do_it_locally {
             let sqlite_and_path = app.sqlite_and_path.read().await;
             let response_message = rxqlite_sqlx_common::do_sql(&sqlite_and_path, message).await;
-            let client_write_response = openraft::raft::ClientWriteResponse::<TypeConfig> {
-                log_id: LogId {
-                    leader_id: LeaderId {
-                        term: u64::MAX,
-                        node_id: u64::MAX,
-                    },
-                    index: u64::MAX,
-                },
-                data: Some(response_message),
-                membership: None,
-            };
-            let res = Result::<
-                openraft::raft::ClientWriteResponse<TypeConfig>,
-                openraft::error::RaftError<u64, openraft::error::ClientWriteError<u64, Node>>,
-            >::Ok(client_write_response);
-            Ok(reply::json(&res))
+            Ok(local_response(response_message))
         } else {
             let server_metrics = app.raft.server_metrics().borrow().clone();
             if let Some(leader_id) = server_metrics.current_leader {
@@ -81,6 +328,22 @@ pub async fn sql_consistent_or_fast(
                         None
                     }
                 });
+                // a node losing the lead may still see itself as the leader
+                let forward_to = leader_node.as_ref().filter(|_| leader_id != app.id);
+                if let (Some(forwarder), None, Some(leader_node)) =
+                    (app.read_forwarder.as_ref(), forwarded_by, forward_to)
+                {
+                    // on failure, the client is left to contact the leader itself
+                    match forward_read(forwarder, app.id, leader_node, &message, &options).await {
+                        Ok(res) => return Ok(reply::json(&res)),
+                        Err(err) => tracing::warn!(
+                            "node {} couldn't forward a read to leader {}: {}",
+                            app.id,
+                            leader_id,
+                            err
+                        ),
+                    }
+                }
                 let res = Result::<
                     openraft::raft::ClientWriteResponse<TypeConfig>,
                     openraft::error::RaftError<u64, openraft::error::ClientWriteError<u64, Node>>,
@@ -113,14 +376,17 @@ pub async fn sql_consistent_or_fast(
 
 pub async fn sql(
     message: Message,
+    options: RequestOptions,
     app: Arc<App>,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
-    sql_consistent_or_fast(message, app, false).await
+    sql_consistent_or_fast(message, options, None, app, false).await
 }
 
 pub async fn sql_consistent(
     message: Message,
+    options: RequestOptions,
+    forwarded_by: Option<NodeId>,
     app: Arc<App>,
 ) -> Result<impl warp::Reply, std::convert::Infallible> {
-    sql_consistent_or_fast(message, app, true).await
+    sql_consistent_or_fast(message, options, forwarded_by, app, true).await
 }
@@ -1,21 +1,23 @@
-//use std::collections::BTreeMap;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use openraft::error::Infallible;
+use openraft::ChangeMembers;
 use openraft::RaftMetrics;
 
 use crate::app::App;
+use crate::compaction::CompactionMetrics;
 use crate::Node;
 use crate::NodeId;
 use warp::reply;
 //use rxqlite_common::{RSQliteNodeConfig};
 
 //use crate::TypeConfig;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 // --- Cluster management
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Empty {}
 
 /// Add a node as **Learner**.
@@ -31,6 +33,8 @@ pub async fn add_learner(
         rpc_addr,
         api_addr,
         //tls_config: None,
+        // the node tells once it runs
+        compressed_snapshots: false,
     };
     let res = app.raft.add_learner(node_id, node, true).await;
     Ok(reply::json(&res))
@@ -45,6 +49,40 @@ pub async fn change_membership(
     Ok(reply::json(&res))
 }
 
+/// The body of `cluster/update-node`.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UpdateNode {
+    Node(NodeId, Node),
+    /// Sent by the nodes that don't tell whether they read compressed snapshots.
+    Addresses(NodeId, String, String),
+}
+
+/// Changes the entry of a node, which restarted with new addresses or tells it reads
+/// compressed snapshots.
+pub async fn update_node(
+    req: UpdateNode,
+    app: Arc<App>,
+) -> Result<impl warp::Reply, std::convert::Infallible> {
+    let (node_id, node) = match req {
+        UpdateNode::Node(node_id, node) => (node_id, node),
+        UpdateNode::Addresses(node_id, api_addr, rpc_addr) => (
+            node_id,
+            Node {
+                rpc_addr,
+                api_addr,
+                compressed_snapshots: false,
+            },
+        ),
+    };
+    let nodes = BTreeMap::from([(node_id, node)]);
+    let res = app
+        .raft
+        .change_membership(ChangeMembers::SetNodes(nodes), false)
+        .await;
+    Ok(reply::json(&res))
+}
+
 /*
 /// Initialize a single-node cluster.
 pub async fn init(_opts: RSQliteNodeConfig, app: Arc<App>) -> Result<impl warp::Reply, std::convert::Infallible> {
@@ -60,10 +98,21 @@ pub async fn init(_opts: RSQliteNodeConfig, app: Arc<App>) -> Result<impl warp::
     Ok(reply::json(&res))
 }
 */
+/// The metrics of raft, and the ones of the compaction of the node next to them.
+#[derive(Serialize)]
+struct NodeMetrics {
+    #[serde(flatten)]
+    raft: RaftMetrics<NodeId, Node>,
+    compaction: CompactionMetrics,
+}
+
 /// Get the latest metrics of the cluster
 pub async fn metrics(app: Arc<App>) -> Result<impl warp::Reply, std::convert::Infallible> {
-    let metrics = app.raft.metrics().borrow().clone();
-    let res: Result<RaftMetrics<NodeId, Node>, Infallible> = Ok(metrics);
+    let metrics = NodeMetrics {
+        raft: app.raft.metrics().borrow().clone(),
+        compaction: app.compaction.metrics(),
+    };
+    let res: Result<NodeMetrics, Infallible> = Ok(metrics);
     Ok(reply::json(&res))
 }
 
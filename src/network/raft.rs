@@ -6,11 +6,68 @@ use openraft::raft::InstallSnapshotRequest;
 use openraft::raft::InstallSnapshotResponse;
 use openraft::raft::VoteRequest;
 use openraft::raft::VoteResponse;
+use openraft::SnapshotMeta;
+use openraft::Vote;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 use toy_rpc_ha421::macros::export_impl;
 
 use crate::app::App;
+use crate::app::LeaderContact;
+use crate::Node;
+use crate::NodeId;
 use crate::TypeConfig;
 
+/// An [`InstallSnapshotRequest`] as sent to the raft service: the rpc codec is json, which
+/// would write each byte of the chunk as a number, so the chunk is encoded in base64.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotChunk {
+    vote: Vote<NodeId>,
+    meta: SnapshotMeta<NodeId, Node>,
+    offset: u64,
+    #[serde(with = "base64_data")]
+    data: Vec<u8>,
+    done: bool,
+}
+
+impl From<InstallSnapshotRequest<TypeConfig>> for SnapshotChunk {
+    fn from(req: InstallSnapshotRequest<TypeConfig>) -> Self {
+        Self {
+            vote: req.vote,
+            meta: req.meta,
+            offset: req.offset,
+            data: req.data,
+            done: req.done,
+        }
+    }
+}
+
+impl From<SnapshotChunk> for InstallSnapshotRequest<TypeConfig> {
+    fn from(chunk: SnapshotChunk) -> Self {
+        Self {
+            vote: chunk.vote,
+            meta: chunk.meta,
+            offset: chunk.offset,
+            data: chunk.data,
+            done: chunk.done,
+        }
+    }
+}
+
+mod base64_data {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Raft protocol service.
 pub struct Raft {
     app: Arc<App>,
@@ -40,21 +97,27 @@ impl Raft {
         req: AppendEntriesRequest<TypeConfig>,
     ) -> Result<AppendEntriesResponse<u64>, toy_rpc_ha421::Error> {
         tracing::debug!("handle append");
-        self.app
-            .raft
-            .append_entries(req)
-            .await
-            .map_err(|e| toy_rpc_ha421::Error::Internal(Box::new(e)))
+        let received_at = Instant::now();
+        let leader_commit = req.leader_commit.map(|log_id| log_id.index);
+        let res = self.app.raft.append_entries(req).await;
+        if let Ok(AppendEntriesResponse::Success | AppendEntriesResponse::PartialSuccess(_)) = &res
+        {
+            self.app.leader_contact.send_replace(Some(LeaderContact {
+                received_at,
+                leader_commit,
+            }));
+        }
+        res.map_err(|e| toy_rpc_ha421::Error::Internal(Box::new(e)))
     }
 
     #[export_method]
     pub async fn snapshot(
         &self,
-        req: InstallSnapshotRequest<TypeConfig>,
+        chunk: SnapshotChunk,
     ) -> Result<InstallSnapshotResponse<u64>, toy_rpc_ha421::Error> {
         self.app
             .raft
-            .install_snapshot(req)
+            .install_snapshot(chunk.into())
             .await
             .map_err(|e| toy_rpc_ha421::Error::Internal(Box::new(e)))
     }
@@ -21,6 +21,7 @@ use serde::de::DeserializeOwned;
 use toy_rpc_ha421::Client;
 
 use super::raft::RaftClientStub;
+use super::raft::SnapshotChunk;
 use crate::Node;
 use crate::NodeId;
 use crate::TypeConfig;
@@ -323,7 +324,7 @@ impl RaftNetwork<TypeConfig> for NetworkConnection {
         self.c()
             .await?
             .raft()
-            .snapshot(req)
+            .snapshot(SnapshotChunk::from(req))
             .await
             .map_err(|e| to_error(e, self.target))
     }
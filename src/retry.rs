@@ -0,0 +1,180 @@
+//! How [`RXQLiteClient`](crate::client::RXQLiteClient) sends a request again after an
+//! error.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The errors after which a request is sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOn {
+    /// The node couldn't be reached, so the request wasn't sent. The client asks the other
+    /// nodes it knows of for the leader before sending it again.
+    pub unreachable: bool,
+    /// The node doesn't know the leader, an election being underway.
+    pub no_leader: bool,
+    /// The request timed out or its response was lost: the node may have executed it, and
+    /// sending a write again may execute it twice, unless the write has a
+    /// [`RequestId`](crate::RequestId).
+    pub network: bool,
+}
+
+impl Default for RetryOn {
+    fn default() -> Self {
+        Self {
+            unreachable: true,
+            no_leader: true,
+            network: false,
+        }
+    }
+}
+
+/// How many times, and for how long, the client tries to send a request.
+///
+/// A request redirected to the leader is sent again at once. Otherwise the client waits
+/// before each new attempt, starting with `initial_backoff` and doubling the wait each
+/// time, up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The number of times a request is sent at most, redirections included.
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Wait between half and all of each backoff, picked at random, so that clients
+    /// failing together don't retry together.
+    pub jitter: bool,
+    /// How long an attempt may last.
+    pub attempt_timeout: Option<Duration>,
+    /// How long a request may last, all attempts included.
+    pub deadline: Option<Duration>,
+    pub retry_on: RetryOn,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter: true,
+            attempt_timeout: Some(Duration::from_secs(30)),
+            deadline: None,
+            retry_on: RetryOn::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends each request once.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+    pub fn backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    pub fn attempt_timeout(mut self, attempt_timeout: Option<Duration>) -> Self {
+        self.attempt_timeout = attempt_timeout;
+        self
+    }
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+    pub fn retry_on(mut self, retry_on: RetryOn) -> Self {
+        self.retry_on = retry_on;
+        self
+    }
+}
+
+/// The attempts made to send a request under a [`RetryPolicy`].
+pub(crate) struct Attempts<'a> {
+    policy: &'a RetryPolicy,
+    attempts: usize,
+    backoff: Duration,
+    deadline: Option<Instant>,
+}
+
+impl<'a> Attempts<'a> {
+    pub(crate) fn new(policy: &'a RetryPolicy) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            backoff: policy.initial_backoff,
+            deadline: policy.deadline.map(|deadline| Instant::now() + deadline),
+        }
+    }
+
+    pub(crate) fn retry_on(&self) -> RetryOn {
+        self.policy.retry_on
+    }
+
+    /// How long the next attempt may last.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.policy.attempt_timeout, left) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        }
+    }
+
+    /// Counts a failed attempt, returning whether there is time for another one.
+    fn fail(&mut self) -> bool {
+        self.attempts += 1;
+        let in_time = match self.deadline {
+            Some(deadline) => Instant::now() < deadline,
+            None => true,
+        };
+        self.attempts < self.policy.max_attempts && in_time
+    }
+
+    /// Counts an attempt redirected to another node, returning whether it may be sent
+    /// there.
+    pub(crate) fn redirect(&mut self) -> bool {
+        self.fail()
+    }
+
+    /// Counts a failed attempt and waits for its backoff, returning whether the request may
+    /// be sent again.
+    pub(crate) async fn backoff(&mut self) -> bool {
+        if !self.fail() {
+            return false;
+        }
+        let backoff = if self.policy.jitter {
+            jittered(self.backoff)
+        } else {
+            self.backoff
+        };
+        self.backoff = (self.backoff * 2).min(self.policy.max_backoff);
+        if let Some(deadline) = self.deadline {
+            if Instant::now() + backoff >= deadline {
+                return false;
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        true
+    }
+}
+
+fn jittered(backoff: Duration) -> Duration {
+    // hashers are keyed at random, which is random enough to spread retries
+    let random = RandomState::new().build_hasher().finish();
+    let half = backoff / 2;
+    half + Duration::from_nanos(random % (half.as_nanos() as u64 + 1))
+}
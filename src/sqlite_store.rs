@@ -1,7 +1,7 @@
 use std::fmt::Debug;
-use std::io::Cursor;
 use std::ops::RangeBounds;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use byteorder::BigEndian;
@@ -33,10 +33,13 @@ use rocksdb::Options;
 use rocksdb::DB;
 use serde::{Deserialize, Serialize};
 
+use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 
 pub use sqlx::{migrate::MigrateDatabase, Pool};
 pub use sqlx_sqlite_cipher::Sqlite;
+use sqlx_sqlite_cipher::{SqliteConnectOptions, SqliteJournalMode};
+use std::str::FromStr;
 
 pub type SqlitePool = Pool<Sqlite>;
 
@@ -46,12 +49,13 @@ use std::path::PathBuf;
 use crate::typ;
 use crate::Node;
 use crate::NodeId;
+use crate::SnapshotCompression;
 use crate::SnapshotData;
 use crate::TypeConfig;
-use rxqlite_sqlx_common::do_sql;
+use rxqlite_common::RXQLiteError;
+use rxqlite_sqlx_common::{begin_read, do_sql};
 
 pub use rxqlite_sqlx_common::SqlxDb;
-use sqlite_snapshot::SqliteSnaphot;
 
 #[derive(Debug, Clone)]
 pub struct SqliteAndPath {
@@ -74,7 +78,9 @@ pub type Response = Option<rxqlite_common::MessageResponse>;
 pub struct StoredSnapshot {
     pub meta: SnapshotMeta<NodeId, Node>,
 
-    /// The data of the state machine at the time of this snapshot.
+    /// The data of the state machine, for the snapshots stored before the data had its own
+    /// file: it's moved to one when the node starts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub data: Vec<u8>,
 }
 
@@ -91,6 +97,16 @@ pub struct StateMachineStore {
     /// State machine stores snapshot in db.
     db: Arc<DB>,
 
+    /// The directory of the snapshot files, named after the ids of the snapshots.
+    snapshot_dir: PathBuf,
+
+    /// Held while the current snapshot changes, for its file to be the one left in
+    /// `snapshot_dir`.
+    snapshot_lock: Arc<Mutex<()>>,
+
+    /// How the snapshots this node builds are compressed.
+    snapshot_compression: SnapshotCompression,
+
     encrypt_data: Option<Arc<Box<dyn EncryptData>>>,
 }
 
@@ -103,25 +119,28 @@ pub struct StateMachineData {
     pub sqlite_and_path: Arc<RwLock<SqliteAndPath>>,
 }
 
-impl RaftSnapshotBuilder<TypeConfig> for StateMachineStore {
-    async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeId>> {
-        let last_applied_log = self.data.last_applied_log_id;
-        let last_membership = self.data.last_membership.clone();
+/// Builds a snapshot of the state machine as it was when the builder was made.
+pub struct SnapshotBuilder {
+    store: StateMachineStore,
+    /// The read transaction the database is copied from, begun along with the builder so
+    /// that the copy holds no entry applied after the last one of the snapshot. In a mutex
+    /// for the builder to be `Sync`, which connections aren't.
+    source: Mutex<Result<sqlx::Transaction<'static, SqlxDb>, RXQLiteError>>,
+}
 
-        let sqlite_json = {
-            let mut sqlite_and_path = self.data.sqlite_and_path.write().await;
-            let snapshot = sqlite_snapshot::make_snapshot(&mut sqlite_and_path)
-                .await
-                .map_err(|e| StorageError::IO {
-                    source: StorageIOError::read(AnyError::error(&format!("{}", e))),
-                })?;
-            serde_json::to_vec(&snapshot).map_err(|e| StorageIOError::read_state_machine(&e))?
-        };
+impl RaftSnapshotBuilder<TypeConfig> for SnapshotBuilder {
+    async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeId>> {
+        let source = self.source.get_mut().as_mut().map_err(|e| StorageError::IO {
+            source: StorageIOError::read(AnyError::error(e.to_string())),
+        })?;
+        let store = &self.store;
+        let last_applied_log = store.data.last_applied_log_id;
+        let last_membership = store.data.last_membership.clone();
 
         let snapshot_id = if let Some(last) = last_applied_log {
-            format!("{}-{}-{}", last.leader_id, last.index, self.snapshot_idx)
+            format!("{}-{}-{}", last.leader_id, last.index, store.snapshot_idx)
         } else {
-            format!("--{}", self.snapshot_idx)
+            format!("--{}", store.snapshot_idx)
         };
 
         let meta = SnapshotMeta {
@@ -130,16 +149,37 @@ impl RaftSnapshotBuilder<TypeConfig> for StateMachineStore {
             snapshot_id,
         };
 
-        let snapshot = StoredSnapshot {
-            meta: meta.clone(),
-            data: sqlite_json.clone(),
+        let path = store.snapshot_path(&meta);
+        let mut partial_path = path.clone().into_os_string();
+        partial_path.push(".part");
+        let file = {
+            let sqlite_and_path = store.data.sqlite_and_path.read().await;
+            sqlite_snapshot::make_snapshot(
+                &sqlite_and_path,
+                source,
+                Path::new(&partial_path),
+                store.members_compression(),
+            )
+            .await
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::read(AnyError::error(e.to_string())),
+            })?
         };
 
-        self.set_current_snapshot_(snapshot)?;
+        let snapshot_lock = store.snapshot_lock.clone();
+        let _current = snapshot_lock.lock().await;
+        tokio::fs::rename(&partial_path, &path)
+            .await
+            .map_err(|e| StorageIOError::write_snapshot(Some(meta.signature()), &e))?;
+        store.set_current_snapshot_(StoredSnapshot {
+            meta: meta.clone(),
+            data: Vec::new(),
+        })?;
+        store.remove_stale_snapshots(&path).await;
 
         Ok(Snapshot {
             meta,
-            snapshot: Box::new(Cursor::new(sqlite_json)),
+            snapshot: Box::new(file),
         })
     }
 }
@@ -148,6 +188,8 @@ impl StateMachineStore {
     async fn new(
         db: Arc<DB>,
         sqlite_and_path: Arc<RwLock<SqliteAndPath>>,
+        snapshot_dir: PathBuf,
+        snapshot_compression: SnapshotCompression,
         encrypt_data: Option<Arc<Box<dyn EncryptData>>>,
     ) -> Result<StateMachineStore, StorageError<NodeId>> {
         let mut sm = Self {
@@ -158,32 +200,92 @@ impl StateMachineStore {
             },
             snapshot_idx: 0,
             db,
+            snapshot_dir,
+            snapshot_lock: Default::default(),
+            snapshot_compression,
             encrypt_data,
         };
 
         let snapshot = sm.get_current_snapshot_()?;
         if let Some(snap) = snapshot {
-            sm.update_state_machine_(snap).await?;
+            let path = sm.snapshot_path(&snap.meta);
+            if !snap.data.is_empty() {
+                sqlite_snapshot::convert_stored_snapshot(&snap.data, &path, snapshot_compression)
+                    .await
+                    .map_err(|e| StorageError::IO {
+                        source: StorageIOError::write(AnyError::error(format!("{}", e))),
+                    })?;
+                sm.set_current_snapshot_(StoredSnapshot {
+                    meta: snap.meta.clone(),
+                    data: Vec::new(),
+                })?;
+            }
+            sm.update_state_machine_(&snap.meta, &path).await?;
         }
 
         Ok(sm)
     }
 
+    /// How the snapshots sent to the members of the cluster are compressed: they aren't as
+    /// long as one of the members doesn't read compressed snapshots.
+    fn members_compression(&self) -> SnapshotCompression {
+        let membership = self.data.last_membership.membership();
+        if membership.nodes().all(|(_, node)| node.compressed_snapshots) {
+            self.snapshot_compression
+        } else {
+            SnapshotCompression::None
+        }
+    }
+
+    /// The file of the snapshot of `meta`.
+    fn snapshot_path(&self, meta: &SnapshotMeta<NodeId, Node>) -> PathBuf {
+        self.snapshot_dir.join(format!("{}.snap", meta.snapshot_id))
+    }
+
+    /// The file a snapshot sent by the leader is written to until it's installed.
+    fn incoming_snapshot_path(&self) -> PathBuf {
+        self.snapshot_dir.join("incoming")
+    }
+
+    /// Removes the files of the snapshots other than the current one, at `current`.
+    ///
+    /// Snapshots being sent to followers are read from files opened before, and a snapshot
+    /// being built isn't named `.snap` yet.
+    async fn remove_stale_snapshots(&self, current: &Path) {
+        let mut entries = match tokio::fs::read_dir(&self.snapshot_dir).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("failed to list snapshots: {}", err);
+                return;
+            }
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path == current || path.extension().and_then(|ext| ext.to_str()) != Some("snap")
+            {
+                continue;
+            }
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                tracing::warn!("failed to remove snapshot {}: {}", path.display(), err);
+            }
+        }
+    }
+
     async fn update_state_machine_(
         &mut self,
-        snapshot: StoredSnapshot,
+        meta: &SnapshotMeta<NodeId, Node>,
+        path: &Path,
     ) -> Result<(), StorageError<NodeId>> {
-        let sqlite_snapshot: SqliteSnaphot = serde_json::from_slice(&snapshot.data)
-            .map_err(|e| StorageIOError::read_snapshot(Some(snapshot.meta.signature()), &e))?;
-        self.data.last_applied_log_id = snapshot.meta.last_log_id;
-        self.data.last_membership = snapshot.meta.last_membership.clone();
-
-        let mut sqlite_and_path = self.data.sqlite_and_path.write().await;
-        sqlite_snapshot::update_database_from_snapshot(&mut sqlite_and_path, &sqlite_snapshot)
+        // reads wait for the database of the snapshot
+        let sqlite_and_path = self.data.sqlite_and_path.write().await;
+        sqlite_snapshot::update_database_from_snapshot(&sqlite_and_path, path)
             .await
             .map_err(|e| StorageError::IO {
-                source: StorageIOError::write(AnyError::error(&format!("{}", e))),
+                source: StorageIOError::write(AnyError::error(e.to_string())),
             })?;
+
+        self.data.last_applied_log_id = meta.last_log_id;
+        self.data.last_membership = meta.last_membership.clone();
         Ok(())
     }
 
@@ -239,7 +341,7 @@ impl StateMachineStore {
 }
 
 impl RaftStateMachine<TypeConfig> for StateMachineStore {
-    type SnapshotBuilder = Self;
+    type SnapshotBuilder = SnapshotBuilder;
 
     async fn applied_state(
         &mut self,
@@ -282,13 +384,29 @@ impl RaftStateMachine<TypeConfig> for StateMachineStore {
 
     async fn get_snapshot_builder(&mut self) -> Self::SnapshotBuilder {
         self.snapshot_idx += 1;
-        self.clone()
+        // entries are applied while the snapshot is built: the copy must not see them
+        let source = {
+            let sqlite_and_path = self.data.sqlite_and_path.read().await;
+            begin_read(&sqlite_and_path.pool).await
+        };
+        SnapshotBuilder {
+            store: self.clone(),
+            source: Mutex::new(source),
+        }
     }
 
     async fn begin_receiving_snapshot(
         &mut self,
-    ) -> Result<Box<Cursor<Vec<u8>>>, StorageError<NodeId>> {
-        Ok(Box::new(Cursor::new(Vec::new())))
+    ) -> Result<Box<SnapshotData>, StorageError<NodeId>> {
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.incoming_snapshot_path())
+            .await
+            .map_err(|e| StorageIOError::write_snapshot(None, &e))?;
+        Ok(Box::new(file))
     }
 
     async fn install_snapshot(
@@ -296,14 +414,30 @@ impl RaftStateMachine<TypeConfig> for StateMachineStore {
         meta: &SnapshotMeta<NodeId, Node>,
         snapshot: Box<SnapshotData>,
     ) -> Result<(), StorageError<NodeId>> {
-        let new_snapshot = StoredSnapshot {
-            meta: meta.clone(),
-            data: snapshot.into_inner(),
-        };
-
-        self.update_state_machine_(new_snapshot.clone()).await?;
+        // the snapshot was streamed into the incoming file
+        snapshot
+            .sync_all()
+            .await
+            .map_err(|e| StorageIOError::write_snapshot(Some(meta.signature()), &e))?;
+        drop(snapshot);
+
+        // the frames of the file are checked before the database is replaced, so an
+        // invalid snapshot leaves the state machine and the current snapshot as they were
+        let incoming_path = self.incoming_snapshot_path();
+        self.update_state_machine_(meta, &incoming_path).await?;
+
+        let path = self.snapshot_path(meta);
+        let snapshot_lock = self.snapshot_lock.clone();
+        let _current = snapshot_lock.lock().await;
+        tokio::fs::rename(&incoming_path, &path)
+            .await
+            .map_err(|e| StorageIOError::write_snapshot(Some(meta.signature()), &e))?;
 
-        self.set_current_snapshot_(new_snapshot)?;
+        self.set_current_snapshot_(StoredSnapshot {
+            meta: meta.clone(),
+            data: Vec::new(),
+        })?;
+        self.remove_stale_snapshots(&path).await;
 
         Ok(())
     }
@@ -311,10 +445,26 @@ impl RaftStateMachine<TypeConfig> for StateMachineStore {
     async fn get_current_snapshot(
         &mut self,
     ) -> Result<Option<Snapshot<TypeConfig>>, StorageError<NodeId>> {
-        let x = self.get_current_snapshot_()?;
-        Ok(x.map(|s| Snapshot {
-            meta: s.meta.clone(),
-            snapshot: Box::new(Cursor::new(s.data.clone())),
+        let _current = self.snapshot_lock.lock().await;
+        let s = match self.get_current_snapshot_()? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let path = self.snapshot_path(&s.meta);
+        // a member that doesn't read compressed snapshots joined since it was built
+        if self.members_compression() == SnapshotCompression::None {
+            sqlite_snapshot::uncompress_snapshot_file(&path)
+                .await
+                .map_err(|e| StorageError::IO {
+                    source: StorageIOError::write(AnyError::error(format!("{}", e))),
+                })?;
+        }
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| StorageIOError::read_snapshot(Some(s.meta.signature()), &e))?;
+        Ok(Some(Snapshot {
+            meta: s.meta,
+            snapshot: Box::new(file),
         }))
     }
 }
@@ -323,6 +473,8 @@ impl RaftStateMachine<TypeConfig> for StateMachineStore {
 pub struct LogStore {
     db: Arc<DB>,
     encrypt_data: Option<Arc<Box<dyn EncryptData>>>,
+    // the bytes of the logs appended, reset by the compaction of the node
+    log_bytes: Arc<AtomicU64>,
 }
 type StorageResult<T> = Result<T, StorageError<NodeId>>;
 
@@ -339,6 +491,11 @@ fn bin_to_id(buf: &[u8]) -> u64 {
 }
 
 impl LogStore {
+    /// The bytes of the logs this store appended, as stored.
+    pub fn log_bytes(&self) -> Arc<AtomicU64> {
+        self.log_bytes.clone()
+    }
+
     fn store(&self) -> &ColumnFamily {
         self.db.cf_handle("store").unwrap()
     }
@@ -562,14 +719,12 @@ impl RaftLogStorage<TypeConfig> for LogStore {
         for entry in entries {
             let id = id_to_bin(entry.log_id.index);
             assert_eq!(bin_to_id(&id), entry.log_id.index);
+            let data = self.encrypt_data.encrypt(
+                serde_json::to_vec(&entry).map_err(|e| StorageIOError::write_logs(&e))?,
+            )?;
+            self.log_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
             self.db
-                .put_cf(
-                    self.logs(),
-                    id,
-                    self.encrypt_data.encrypt(
-                        serde_json::to_vec(&entry).map_err(|e| StorageIOError::write_logs(&e))?,
-                    )?,
-                )
+                .put_cf(self.logs(), id, data)
                 .map_err(|e| StorageIOError::write_logs(&e))?;
         }
 
@@ -606,17 +761,22 @@ impl RaftLogStorage<TypeConfig> for LogStore {
     }
 }
 
+/// Opens the database at `db_url` in WAL mode, for the snapshots copying it not to block
+/// the entries applied meanwhile.
 pub async fn init_sqlite_connection(db_url: &str) -> Result<SqlitePool, sqlx::Error> {
     if !Sqlite::database_exists(db_url).await.unwrap_or(false) {
         Sqlite::create_database(db_url).await?;
     }
-    let pool = SqlitePool::connect(db_url).await?;
+    let options = SqliteConnectOptions::from_str(db_url)?.journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePool::connect_with(options).await?;
     Ok(pool)
 }
 
 pub(crate) async fn new_storage<P: AsRef<Path>>(
     rocksdb_path: P,
     sqlite_path: P,
+    snapshot_dir: P,
+    snapshot_compression: SnapshotCompression,
     #[cfg(feature = "sqlcipher")] key: Option<String>,
     encrypt_data: Option<Arc<Box<dyn EncryptData>>>,
 ) -> Result<(LogStore, StateMachineStore), std::io::Error> {
@@ -652,6 +812,8 @@ pub(crate) async fn new_storage<P: AsRef<Path>>(
 
     let db = Arc::new(db);
 
+    tokio::fs::create_dir_all(&snapshot_dir).await?;
+
     let pool = init_sqlite_connection(sqlite_path.to_str().unwrap()).await;
     if let Err(err) = &pool {
         return Err(std::io::Error::new(
@@ -667,10 +829,17 @@ pub(crate) async fn new_storage<P: AsRef<Path>>(
     let log_store = LogStore {
         db: db.clone(),
         encrypt_data: encrypt_data.clone(),
+        log_bytes: Default::default(),
     };
-    let sm_store = StateMachineStore::new(db, sqlite_and_path, encrypt_data)
-        .await
-        .unwrap();
+    let sm_store = StateMachineStore::new(
+        db,
+        sqlite_and_path,
+        snapshot_dir.as_ref().to_path_buf(),
+        snapshot_compression,
+        encrypt_data,
+    )
+    .await
+    .unwrap();
 
     Ok((log_store, sm_store))
 }
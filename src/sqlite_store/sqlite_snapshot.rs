@@ -1,84 +1,351 @@
+//! A snapshot is a file holding a copy of the database, which the leader sends to followers
+//! by chunks, neither of them holding more than a frame of it in memory.
+//!
+//! The file starts with [`MAGIC`] and [`VERSION`], followed by frames: the length of the
+//! frame, the crc32 of its bytes, both as big endian `u32`, then the bytes of the database
+//! it holds. A frame of length 0 ends the file, its crc32 being the one of the whole
+//! database, so that truncated and corrupted snapshots are rejected before being installed.
+//!
+//! Compressed snapshots start with [`COMPRESSED_VERSION`] and the byte of their compression
+//! instead, and their frames tell the length of their compressed bytes after their own.
+//! Uncompressed snapshots keep the first layout, for the nodes that don't read the second:
+//! they are sent uncompressed snapshots, see [`Node::compressed_snapshots`].
+
 use super::*;
 use serde::{Deserialize, Serialize};
-//use crate::sqlite_store::{SqliteAndPath,init_sqlite_connection};
-
+use std::io::SeekFrom;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{BufReader, BufWriter};
+
+use rxqlite_sqlx_common::backup_database;
+
+/// The first bytes of a snapshot file.
+const MAGIC: &[u8; 8] = b"RXQLSNAP";
+
+/// The version of the layout of uncompressed snapshot files.
+const VERSION: u32 = 1;
+
+/// The version of the layout of compressed snapshot files.
+const COMPRESSED_VERSION: u32 = 2;
+
+/// The compressions of snapshot files.
+const ZSTD: u8 = 1;
+const LZ4: u8 = 2;
 
+/// The most bytes of the database a frame holds.
+const FRAME_SIZE: usize = 1 << 20;
+
+/// The most bytes a compressed frame holds, above [`FRAME_SIZE`] for the frames that don't
+/// compress.
+const MAX_STORED_FRAME_SIZE: usize = 2 * FRAME_SIZE;
+
+type SnapshotResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+/// A snapshot as stored in rocksdb before snapshots had their own file.
 #[derive(Serialize, Deserialize, Default)]
 pub struct SqliteSnaphot {
     db: Vec<u8>,
 }
-/*
-PRAGMA schema.wal_checkpoint;
-PRAGMA schema.wal_checkpoint(PASSIVE);
-PRAGMA schema.wal_checkpoint(FULL);
-PRAGMA schema.wal_checkpoint(RESTART);
-PRAGMA schema.wal_checkpoint(TRUNCATE);
-
-If the write-ahead log is enabled (via the journal_mode pragma), this pragma causes a checkpoint operation to run on database database, or on all attached databases if database is omitted. If write-ahead log mode is disabled, this pragma is a harmless no-op.
-
-Invoking this pragma without an argument is equivalent to calling the sqlite3_wal_checkpoint() C interface.
-
-Invoking this pragma with an argument is equivalent to calling the sqlite3_wal_checkpoint_v2() C interface with a 3rd parameter corresponding to the argument:
-PASSIVE
-Checkpoint as many frames as possible without waiting for any database readers or writers to finish. Sync the db file if all frames in the log are checkpointed. This mode is the same as calling the sqlite3_wal_checkpoint() C interface. The busy-handler callback is never invoked in this mode.
-FULL
-This mode blocks (invokes the busy-handler callback) until there is no database writer and all readers are reading from the most recent database snapshot. It then checkpoints all frames in the log file and syncs the database file. FULL blocks concurrent writers while it is running, but readers can proceed.
-RESTART
-This mode works the same way as FULL with the addition that after checkpointing the log file it blocks (calls the busy-handler callback) until all readers are finished with the log file. This ensures that the next client to write to the database file restarts the log file from the beginning. RESTART blocks concurrent writers while it is running, but allowed readers to proceed.
-TRUNCATE
-This mode works the same way as RESTART with the addition that the WAL file is truncated to zero bytes upon successful completion.
-The wal_checkpoint pragma returns a single row with three integer columns. The first column is usually 0 but will be 1 if a RESTART or FULL or TRUNCATE checkpoint was blocked from completing, for example because another thread or process was actively using the database. In other words, the first column is 0 if the equivalent call to sqlite3_wal_checkpoint_v2() would have returned SQLITE_OK or 1 if the equivalent call would have returned SQLITE_BUSY. The second column is the number of modified pages that have been written to the write-ahead log file. The third column is the number of pages in the write-ahead log file that have been successfully moved back into the database file at the conclusion of the checkpoint. The second and third column are -1 if there is no write-ahead log, for example if this pragma is invoked on a database connection that is not in WAL mode.
-*/
-async fn flush_wal(pool: &SqlitePool) -> Result<(i64, i64, i64), sqlx::Error> {
-    let row: (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint;")
-        .fetch_one(pool)
-        .await?;
 
-    Ok(row)
+/// The path of a temporary database next to the one of `sqlite_and_path`, and the url to
+/// open it with the same options, such as the key of the database.
+fn temporary_database(sqlite_and_path: &SqliteAndPath, suffix: &str) -> (PathBuf, String) {
+    let url = sqlite_and_path.path.to_str().unwrap();
+    let (path, options) = match url.find('?') {
+        Some(options_start) => url.split_at(options_start),
+        None => (url, ""),
+    };
+    let path = format!("{}.{}", path, suffix);
+    let url = format!("{}{}", path, options);
+    (PathBuf::from(path), url)
 }
 
-pub async fn make_snapshot(
-    sqlite_and_path: &mut SqliteAndPath,
-) -> Result<SqliteSnaphot, Box<dyn std::error::Error + Send + Sync + 'static>> {
+async fn remove_database(path: &Path) -> std::io::Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        match tokio::fs::remove_file(&file).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn invalid_snapshot(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// The bytes a frame of the database is stored as.
+fn compress(compression: SnapshotCompression, frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    match compression {
+        SnapshotCompression::None => Ok(frame.to_vec()),
+        SnapshotCompression::Zstd(level) => zstd::bulk::compress(frame, level),
+        SnapshotCompression::Lz4 => Ok(lz4_flex::block::compress(frame)),
+    }
+}
+
+/// Decompresses the bytes a frame is `stored` as into `frame`, as long as the frame.
+fn decompress(compression: u8, stored: &[u8], frame: &mut [u8]) -> std::io::Result<()> {
+    let len = match compression {
+        ZSTD => zstd::bulk::decompress_to_buffer(stored, frame)?,
+        _ => lz4_flex::block::decompress_into(stored, frame)
+            .map_err(|err| invalid_snapshot(&err.to_string()))?,
+    };
+    if len != frame.len() {
+        return Err(invalid_snapshot("snapshot frame length mismatch"));
+    }
+    Ok(())
+}
+
+/// Writes the snapshot file at `path` from the bytes of `database`, and returns it opened
+/// at its start.
+async fn write_snapshot_file<R: AsyncRead + Unpin>(
+    path: &Path,
+    mut database: R,
+    compression: SnapshotCompression,
+) -> std::io::Result<File> {
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(MAGIC).await?;
+    match compression {
+        SnapshotCompression::None => writer.write_u32(VERSION).await?,
+        SnapshotCompression::Zstd(_) => {
+            writer.write_u32(COMPRESSED_VERSION).await?;
+            writer.write_u8(ZSTD).await?;
+        }
+        SnapshotCompression::Lz4 => {
+            writer.write_u32(COMPRESSED_VERSION).await?;
+            writer.write_u8(LZ4).await?;
+        }
+    }
+    let mut hasher = crc32fast::Hasher::new();
+    let mut frame = Vec::with_capacity(FRAME_SIZE);
     loop {
-        let status = flush_wal(&sqlite_and_path.pool).await?;
-        if status.0 == 0 {
+        frame.clear();
+        (&mut database)
+            .take(FRAME_SIZE as u64)
+            .read_to_end(&mut frame)
+            .await?;
+        if frame.is_empty() {
             break;
         }
-        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        hasher.update(&frame);
+        let stored = if compression == SnapshotCompression::None {
+            None
+        } else {
+            // compressing a frame takes long enough to keep it off the threads of the runtime
+            let (uncompressed, stored) = tokio::task::spawn_blocking(move || {
+                let stored = compress(compression, &frame);
+                (frame, stored)
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+            frame = uncompressed;
+            Some(stored?)
+        };
+        writer.write_u32(frame.len() as u32).await?;
+        if let Some(stored) = &stored {
+            writer.write_u32(stored.len() as u32).await?;
+        }
+        writer.write_u32(crc32fast::hash(&frame)).await?;
+        writer.write_all(stored.as_deref().unwrap_or(&frame)).await?;
     }
-
-    sqlite_and_path.pool.close().await;
-    let mut sqlite_snapshot = SqliteSnaphot::default();
-    {
-        let mut file = File::open(&sqlite_and_path.path).await?;
-        file.read_to_end(&mut sqlite_snapshot.db).await?;
-        file.flush().await?;
+    writer.write_u32(0).await?;
+    if compression != SnapshotCompression::None {
+        writer.write_u32(0).await?;
     }
-    sqlite_and_path.pool = init_sqlite_connection(sqlite_and_path.path.to_str().unwrap()).await?;
-    Ok(sqlite_snapshot)
+    writer.write_u32(hasher.finalize()).await?;
+    writer.flush().await?;
+    let mut file = writer.into_inner();
+    file.sync_all().await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(file)
 }
 
-pub async fn update_database_from_snapshot(
-    sqlite_and_path: &mut SqliteAndPath,
-    sqlite_snapshot: &SqliteSnaphot,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+/// Writes the bytes of the database held by the snapshot file at `path` to `database`,
+/// checking them against their checksums.
+async fn read_snapshot_file<W: AsyncWrite + Unpin>(
+    path: &Path,
+    database: &mut W,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(File::open(path).await?);
+    let mut magic = [0_u8; MAGIC.len()];
+    reader.read_exact(&mut magic).await?;
+    if &magic != MAGIC {
+        return Err(invalid_snapshot("not a snapshot file"));
+    }
+    let compression = match reader.read_u32().await? {
+        VERSION => None,
+        COMPRESSED_VERSION => match reader.read_u8().await? {
+            compression @ (ZSTD | LZ4) => Some(compression),
+            compression => {
+                return Err(invalid_snapshot(&format!(
+                    "unsupported snapshot compression {}",
+                    compression
+                )))
+            }
+        },
+        version => {
+            return Err(invalid_snapshot(&format!(
+                "unsupported snapshot version {}",
+                version
+            )))
+        }
+    };
+    let mut hasher = crc32fast::Hasher::new();
+    let mut frame = Vec::with_capacity(FRAME_SIZE);
+    let mut stored = Vec::new();
     loop {
-        let status = flush_wal(&sqlite_and_path.pool).await?;
-        if status.0 == 0 {
+        let len = reader.read_u32().await? as usize;
+        let stored_len = match compression {
+            Some(_) => reader.read_u32().await? as usize,
+            None => len,
+        };
+        let checksum = reader.read_u32().await?;
+        if len == 0 {
+            if checksum != hasher.finalize() {
+                return Err(invalid_snapshot("snapshot checksum mismatch"));
+            }
             break;
         }
-        tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+        if len > FRAME_SIZE || stored_len > MAX_STORED_FRAME_SIZE {
+            return Err(invalid_snapshot("snapshot frame too large"));
+        }
+        frame.resize(len, 0);
+        match compression {
+            Some(compression) => {
+                stored.resize(stored_len, 0);
+                reader.read_exact(&mut stored).await?;
+                decompress(compression, &stored, &mut frame)?;
+            }
+            None => {
+                reader.read_exact(&mut frame).await?;
+            }
+        }
+        if crc32fast::hash(&frame) != checksum {
+            return Err(invalid_snapshot("snapshot frame checksum mismatch"));
+        }
+        hasher.update(&frame);
+        database.write_all(&frame).await?;
+    }
+    database.flush().await
+}
+
+/// Copies the database into the snapshot file at `path` with the backup api of sqlite, the
+/// pool staying open for reads and writes meanwhile, and returns the file opened at its
+/// start.
+///
+/// The database is copied as `source`, a connection of the pool in a read transaction,
+/// sees it.
+pub async fn make_snapshot(
+    sqlite_and_path: &SqliteAndPath,
+    source: &mut <SqlxDb as sqlx::Database>::Connection,
+    path: &Path,
+    compression: SnapshotCompression,
+) -> SnapshotResult<File> {
+    let (copy_path, url) = temporary_database(sqlite_and_path, "snapshot");
+    remove_database(&copy_path).await?;
+    let copy = init_sqlite_connection(&url).await?;
+    let backup = {
+        let mut destination = copy.acquire().await?;
+        backup_database(source, &mut destination).await
+    };
+    // closing the last connection checkpoints the copy into its file
+    copy.close().await;
+    let snapshot = match backup {
+        Ok(()) => match File::open(&copy_path).await {
+            Ok(database) => {
+                write_snapshot_file(path, BufReader::new(database), compression).await
+            }
+            Err(err) => Err(err),
+        },
+        Err(err) => {
+            remove_database(&copy_path).await?;
+            return Err(err.into());
+        }
+    };
+    remove_database(&copy_path).await?;
+    Ok(snapshot?)
+}
+
+/// Writes the snapshot file at `path` from a snapshot stored before snapshots had their
+/// own file.
+pub async fn convert_stored_snapshot(
+    data: &[u8],
+    path: &Path,
+    compression: SnapshotCompression,
+) -> SnapshotResult<()> {
+    let snapshot: SqliteSnaphot = serde_json::from_slice(data)?;
+    write_snapshot_file(path, snapshot.db.as_slice(), compression).await?;
+    Ok(())
+}
+
+/// Rewrites the snapshot file at `path` uncompressed if it is compressed.
+pub async fn uncompress_snapshot_file(path: &Path) -> SnapshotResult<()> {
+    let mut header = [0_u8; MAGIC.len() + 4];
+    File::open(path).await?.read_exact(&mut header).await?;
+    if header[MAGIC.len()..] != COMPRESSED_VERSION.to_be_bytes() {
+        return Ok(());
     }
-    sqlite_and_path.pool.close().await;
-    tokio::fs::remove_file(&sqlite_and_path.path).await?;
-    {
-        let mut file = File::create(&sqlite_and_path.path).await?;
-        file.write_all(&sqlite_snapshot.db).await?;
-        file.flush().await?;
+    let mut uncompressed_path = path.as_os_str().to_owned();
+    uncompressed_path.push(".part");
+    let uncompressed_path = PathBuf::from(uncompressed_path);
+    let (mut database, uncompressed) = tokio::io::duplex(FRAME_SIZE);
+    let read = async move {
+        let read = read_snapshot_file(path, &mut database).await;
+        // the end of the stream ends the uncompressed file
+        drop(database);
+        read
+    };
+    let write = write_snapshot_file(&uncompressed_path, uncompressed, SnapshotCompression::None);
+    match tokio::join!(read, write) {
+        (Ok(()), Ok(_)) => tokio::fs::rename(&uncompressed_path, path).await?,
+        (Err(err), _) | (_, Err(err)) => {
+            let _ = tokio::fs::remove_file(&uncompressed_path).await;
+            return Err(err.into());
+        }
     }
-    sqlite_and_path.pool = init_sqlite_connection(sqlite_and_path.path.to_str().unwrap()).await?;
     Ok(())
 }
+
+/// Replaces the database with the one of the snapshot file at `path`, with the backup api
+/// of sqlite. The whole file is checked before the database changes.
+///
+/// The caller keeps the database from being used meanwhile.
+pub async fn update_database_from_snapshot(
+    sqlite_and_path: &SqliteAndPath,
+    path: &Path,
+) -> SnapshotResult<()> {
+    let (restore_path, url) = temporary_database(sqlite_and_path, "restore");
+    remove_database(&restore_path).await?;
+    let restore = async {
+        let mut database = BufWriter::new(File::create(&restore_path).await?);
+        read_snapshot_file(path, &mut database).await?;
+        database.into_inner().sync_all().await
+    };
+    let backup = match restore.await {
+        Ok(()) => match init_sqlite_connection(&url).await {
+            Ok(snapshot) => {
+                let backup = {
+                    let mut source = snapshot.acquire().await?;
+                    let mut destination = sqlite_and_path.pool.acquire().await?;
+                    backup_database(&mut source, &mut destination).await
+                };
+                snapshot.close().await;
+                backup.map_err(Into::into)
+            }
+            Err(err) => Err(err.into()),
+        },
+        Err(err) => Err(err.into()),
+    };
+    remove_database(&restore_path).await?;
+    backup
+}
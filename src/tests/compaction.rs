@@ -0,0 +1,76 @@
+use super::*;
+use crate::CompactionPolicy;
+use sqlx::types::chrono::Utc;
+
+fn do_compaction_policy(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    // the policy is read from the config file, the flags overriding it
+    let config_path = std::env::temp_dir().join(format!("{}.toml", test_name));
+    std::fs::write(
+        &config_path,
+        "[compaction]\nsnapshot_logs_since_last = 0\nsnapshot_log_bytes = 4096\n\
+         max_in_snapshot_log_to_keep = 100\n",
+    )
+    .unwrap();
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &[
+                "--config",
+                config_path.to_str().unwrap(),
+                "--max-in-snapshot-log-to-keep",
+                "0",
+            ],
+            TEST_USER_TABLE,
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        let compaction = client.node_compaction_metrics().await.unwrap();
+        assert_eq!(
+            compaction.policy,
+            CompactionPolicy {
+                snapshot_logs_since_last: 0,
+                snapshot_log_bytes: 4096,
+                max_in_snapshot_log_to_keep: 0,
+                ..Default::default()
+            }
+        );
+
+        let insert = "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)";
+        for i in 0..50 {
+            client
+                .execute(insert, vec![format!("Ha{}", i).into(), Utc::now().into()])
+                .await
+                .unwrap();
+        }
+
+        // the logs appended reach the threshold: the leader builds a snapshot without being
+        // asked to, then purges the logs it includes
+        let mut compacted = false;
+        for _ in 0..30 {
+            let metrics = tm.get_metrics(1).await.unwrap();
+            if metrics.snapshot.is_some() && metrics.purged == metrics.snapshot {
+                compacted = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        assert!(compacted, "the logs weren't compacted");
+    });
+    std::fs::remove_file(&config_path).unwrap();
+}
+
+#[test]
+fn compaction_policy() {
+    do_compaction_policy("compaction_policy", None);
+}
+
+#[test]
+fn compaction_policy_insecure_ssl() {
+    do_compaction_policy(
+        "compaction_policy_insecure_ssl",
+        Some(TestTlsConfig::default().accept_invalid_certificates(true)),
+    );
+}
@@ -0,0 +1,121 @@
+use super::*;
+use crate::RXQLite;
+use sqlx::error::ErrorKind;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Pool, Row};
+
+fn do_sqlx_driver(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config.clone());
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let instance = tm.instances.get(&1).unwrap();
+        let mut url = format!("rxqlite://{}?leader_id=1", instance.http_addr);
+        if let Some(tls_config) = tls_config.as_ref() {
+            url.push_str("&tls=yes");
+            if tls_config.accept_invalid_certificates {
+                url.push_str("&accept_invalid_certificates=yes");
+            }
+        }
+        let pool = Pool::<RXQLite>::connect(&url).await.unwrap();
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      nickname TEXT,
+      birth_date DATETIME NOT NULL
+      )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let birth_date = DateTime::parse_from_rfc3339("2000-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let result =
+            sqlx::query("INSERT INTO _test_user_ (name,nickname,birth_date) VALUES (?,?,?)")
+                .bind("Ha")
+                .bind(None::<String>)
+                .bind(birth_date)
+                .execute(&pool)
+                .await
+                .unwrap();
+        assert_eq!(result.rows_affected(), 1);
+        assert_eq!(result.last_insert_rowid(), 1);
+
+        let rows =
+            sqlx::query("SELECT id,name,nickname,birth_date FROM _test_user_ WHERE name = ?")
+                .bind("Ha")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.try_get::<i64, _>("id").unwrap(), 1);
+        assert_eq!(row.try_get::<&str, _>("name").unwrap(), "Ha");
+        assert_eq!(row.try_get::<Option<String>, _>("nickname").unwrap(), None);
+        assert_eq!(
+            row.try_get::<DateTime<Utc>, _>("birth_date").unwrap(),
+            birth_date
+        );
+        assert!(row.try_get::<i64, _>("name").is_err());
+
+        let (name,): (String,) = sqlx::query_as("SELECT name FROM _test_user_ WHERE id = ?")
+            .bind(1_i64)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(&name, "Ha");
+
+        let row = sqlx::query("SELECT name FROM _test_user_ WHERE id = ?")
+            .bind(2_i64)
+            .fetch_optional(&pool)
+            .await
+            .unwrap();
+        assert!(row.is_none());
+
+        let err = sqlx::query("INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)")
+            .bind("Ha")
+            .bind(birth_date)
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        match err {
+            sqlx::Error::Database(err) => assert_eq!(err.kind(), ErrorKind::UniqueViolation),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        // writes yield the rows of their RETURNING clause along with their query result
+        let (id,): (i64,) = sqlx::query_as(
+            "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?) RETURNING id",
+        )
+        .bind("Ho")
+        .bind(birth_date)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(id, 2);
+        let result = sqlx::query("UPDATE _test_user_ SET nickname = name RETURNING id")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(result.rows_affected(), 2);
+
+        pool.close().await;
+    });
+}
+
+#[test]
+fn sqlx_driver() {
+    do_sqlx_driver("sqlx_driver", None);
+}
+
+#[test]
+fn sqlx_driver_insecure_ssl() {
+    do_sqlx_driver(
+        "sqlx_driver_insecure_ssl",
+        Some(TestTlsConfig::default().accept_invalid_certificates(true)),
+    );
+}
@@ -4,6 +4,7 @@ use crate::typ;
 use crate::NodeId;
 use futures::future::join_all;
 use openraft::LogId;
+use rxqlite_common::{ExecuteResult, Message, MessageResponse, Rows};
 use rxqlite_tests_common::*;
 use std::collections::HashMap;
 use std::env;
@@ -20,6 +21,21 @@ mod queries;
 #[cfg(not(feature = "test-dependency"))]
 mod notifications;
 
+#[cfg(not(feature = "test-dependency"))]
+mod driver;
+
+#[cfg(not(feature = "test-dependency"))]
+mod retries;
+
+#[cfg(not(feature = "test-dependency"))]
+mod snapshots;
+
+#[cfg(not(feature = "test-dependency"))]
+mod compaction;
+
+#[cfg(not(feature = "test-dependency"))]
+mod restart;
+
 #[cfg(target_os = "windows")]
 const EXE_SUFFIX: &str = ".exe";
 
@@ -34,11 +50,26 @@ const DELAY_BETWEEN_LEADER_VACATION_RETRIES: tokio::time::Duration =
 
 const LEADER_VACATION_RETRIES: usize = 5;
 
+/// The table most tests write to.
+const TEST_USER_TABLE: &str = "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      birth_date DATETIME NOT NULL
+      )";
 
 pub fn get_cluster_manager(
     test_name: &str,
     instance_count: usize,
     tls_config: Option<TestTlsConfig>,
+) -> anyhow::Result<TestClusterManager> {
+    get_cluster_manager_with_args(test_name, instance_count, tls_config, &[])
+}
+
+pub fn get_cluster_manager_with_args(
+    test_name: &str,
+    instance_count: usize,
+    tls_config: Option<TestTlsConfig>,
+    extra_args: &[&str],
 ) -> anyhow::Result<TestClusterManager> {
     let executable_path = if let Ok(rxqlited_dir) = std::env::var("RXQLITED_DIR") {
         let executable_path = PathBuf::from(rxqlited_dir).join(format!("rxqlited{}", EXE_SUFFIX));
@@ -56,12 +87,13 @@ pub fn get_cluster_manager(
     assert!(executable_path.is_file());
     let temp_dir = env::temp_dir();
     let working_directory = temp_dir.join(test_name);
-    TestClusterManager::new(
+    TestClusterManager::new_with_args(
         instance_count,
         &working_directory,
         &executable_path,
         "127.0.0.1",
         tls_config,
+        extra_args,
     )
 }
 
@@ -86,6 +118,43 @@ impl std::ops::DerefMut for TestManager {
 impl TestManager {
     pub fn new(test_name: &str, instance_count: usize, tls_config: Option<TestTlsConfig>) -> Self {
         let tcm = get_cluster_manager(test_name, instance_count, tls_config.clone()).unwrap();
+        Self::with_cluster_manager(tcm, tls_config)
+    }
+
+    /// Like [`TestManager::new`], initializing every node with `extra_args`.
+    pub fn new_with_args(
+        test_name: &str,
+        instance_count: usize,
+        tls_config: Option<TestTlsConfig>,
+        extra_args: &[&str],
+    ) -> Self {
+        let tcm = get_cluster_manager_with_args(
+            test_name,
+            instance_count,
+            tls_config.clone(),
+            extra_args,
+        )
+        .unwrap();
+        Self::with_cluster_manager(tcm, tls_config)
+    }
+
+    /// Starts a cluster of 3 nodes initialized with `extra_args`, waits for it to be
+    /// established and creates a table with `create_table` through node 1.
+    pub async fn with_table(
+        test_name: &str,
+        tls_config: Option<TestTlsConfig>,
+        extra_args: &[&str],
+        create_table: &str,
+    ) -> Self {
+        let tm = Self::new_with_args(test_name, 3, tls_config, extra_args);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+        let message = Message::Execute(create_table.into(), vec![].into());
+        sql_with_retries(client, &message).await;
+        tm
+    }
+
+    fn with_cluster_manager(tcm: TestClusterManager, tls_config: Option<TestTlsConfig>) -> Self {
         let clients: HashMap<NodeId, RXQLiteClient> = tcm
             .instances
             .iter()
@@ -182,3 +251,41 @@ impl TestManager {
         Err(anyhow::anyhow!("wait_for_last_applied_log timeout"))
     }
 }
+
+/// Sends `message` through `client`, retrying while the cluster has no leader, and panics
+/// if the request or the statement fails.
+pub async fn sql_with_retries(
+    client: &RXQLiteClient,
+    message: &Message,
+) -> typ::ClientWriteResponse {
+    let response = client
+        .sql_with_retries_and_delay(
+            message,
+            LEADER_VACATION_RETRIES,
+            DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+        )
+        .await
+        .unwrap();
+    if let Some(MessageResponse::Error(err)) = &response.data {
+        panic!("{}", err);
+    }
+    response
+}
+
+/// The rows answered in `response`, panicking on any other answer.
+pub fn expect_rows(response: typ::ClientWriteResponse) -> Rows {
+    match response.data.unwrap() {
+        MessageResponse::Rows(rows) => rows,
+        MessageResponse::Error(err) => panic!("{}", err),
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
+
+/// The result of the statement answered in `response`, panicking on any other answer.
+pub fn expect_executed(response: typ::ClientWriteResponse) -> ExecuteResult {
+    match response.data.unwrap() {
+        MessageResponse::Executed(result) => result,
+        MessageResponse::Error(err) => panic!("{}", err),
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
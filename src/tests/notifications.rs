@@ -29,7 +29,7 @@ fn do_notifications(test_name: &str,
       birth_date DATETIME NOT NULL
       )"
             .into(),
-            vec![],
+            vec![].into(),
         );
         let response = client.sql_with_retries_and_delay(&message,
           LEADER_VACATION_RETRIES,
@@ -40,14 +40,15 @@ fn do_notifications(test_name: &str,
 
         let message = response.data.unwrap();
         match message {
-            MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+            MessageResponse::Executed(_) => {}
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
         let name = "Ha";
         let birth_date = Utc::now();
         let message = Message::Execute(
             "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".into(),
-            vec![name.into(), birth_date.into()],
+            vec![name.into(), birth_date.into()].into(),
         );
         let response = client.sql_with_retries_and_delay(&message,
           LEADER_VACATION_RETRIES,
@@ -55,8 +56,9 @@ fn do_notifications(test_name: &str,
         ).await.unwrap();
         let message = response.data.unwrap();
         match message {
-            MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+            MessageResponse::Executed(_) => {}
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
 
         //tm.wait_for_last_applied_log(response.log_id,60).await.unwrap();
@@ -84,7 +86,7 @@ fn do_notifications(test_name: &str,
         };
         let message = Message::Execute(
             "DELETE FROM _test_user_ WHERE name = ?".into(),
-            vec![name.into()],
+            vec![name.into()].into(),
         );
         let response = client.sql_with_retries_and_delay(&message,
           LEADER_VACATION_RETRIES,
@@ -92,8 +94,9 @@ fn do_notifications(test_name: &str,
         ).await.unwrap();
         let message = response.data.unwrap();
         match message {
-            MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+            MessageResponse::Executed(_) => {}
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
         let notification_stream = client.notification_stream.as_mut().unwrap();
         let message = notification_stream
@@ -150,7 +153,7 @@ fn do_notifications2(test_name: &str,
         birth_date DATETIME NOT NULL
         )"
               .into(),
-              vec![],
+              vec![].into(),
           );
           let response = client.sql_with_retries_and_delay(&message,
           LEADER_VACATION_RETRIES,
@@ -159,14 +162,15 @@ fn do_notifications2(test_name: &str,
 
           let message = response.data.unwrap();
           match message {
-              MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+              MessageResponse::Executed(_) => {}
               MessageResponse::Error(err) => panic!("{}", err),
+              other => panic!("unexpected response: {:?}", other),
           }
           let name = "Ha";
           let birth_date = Utc::now();
           let message = Message::Execute(
               "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".into(),
-              vec![name.into(), birth_date.into()],
+              vec![name.into(), birth_date.into()].into(),
           );
           let response = client.sql_with_retries_and_delay(&message,
             LEADER_VACATION_RETRIES,
@@ -174,8 +178,9 @@ fn do_notifications2(test_name: &str,
           ).await.unwrap();
           let message = response.data.unwrap();
           match message {
-              MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+              MessageResponse::Executed(_) => {}
               MessageResponse::Error(err) => panic!("{}", err),
+              other => panic!("unexpected response: {:?}", other),
           }
 
           //tm.wait_for_last_applied_log(response.log_id,60).await.unwrap();
@@ -203,7 +208,7 @@ fn do_notifications2(test_name: &str,
           };
           let message = Message::Execute(
               "DELETE FROM _test_user_ WHERE name = ?".into(),
-              vec![name.into()],
+              vec![name.into()].into(),
           );
           let response = client.sql_with_retries_and_delay(&message,
             LEADER_VACATION_RETRIES,
@@ -211,8 +216,9 @@ fn do_notifications2(test_name: &str,
           ).await.unwrap();
           let message = response.data.unwrap();
           match message {
-              MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+              MessageResponse::Executed(_) => {}
               MessageResponse::Error(err) => panic!("{}", err),
+              other => panic!("unexpected response: {:?}", other),
           }
           let notification_stream = client.notification_stream.as_mut().unwrap();
           let message = notification_stream
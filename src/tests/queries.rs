@@ -1,5 +1,6 @@
 use super::*;
-use rxqlite_common::{Message, MessageResponse};
+use crate::{ConstraintKind, ErrorKind};
+use rxqlite_common::{Message, MessageResponse, RequestOptions, RowError, Value, ValueError};
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::{Pool, Row};
 use sqlx_sqlite_cipher::Sqlite;
@@ -7,8 +8,29 @@ use sqlx_sqlite_cipher::Sqlite;
 use base64::{engine::general_purpose::URL_SAFE, Engine as _};
 use ring::digest;
 use std::fs::File;
+use std::collections::BTreeMap;
 use std::io::BufReader;
 
+/// The parameter opening the sqlite database of an instance: in tests, all instances share
+/// the same key, so their databases are encrypted with the same key.
+fn sqlite_key_param(tm: &TestManager) -> String {
+    if tm.tls_config.is_some() {
+        let private_key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(
+            &mut File::open(&tm.key_path).unwrap(),
+        ))
+        .filter_map(|x| x.ok())
+        .next()
+        .unwrap();
+
+        let hashed_key = digest::digest(&digest::SHA256, private_key.secret_pkcs8_der());
+
+        let hashed_key = URL_SAFE.encode(hashed_key.as_ref());
+        format!("?key=\"{}\"", hashed_key)
+    } else {
+        String::new()
+    }
+}
+
 fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
     let rt = Runtime::new().unwrap();
     let _ = rt.block_on(async {
@@ -26,7 +48,7 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
       birth_date DATETIME NOT NULL
       )"
             .into(),
-            vec![],
+            vec![].into(),
         );
         let response = client.sql_with_retries_and_delay(&message,
             LEADER_VACATION_RETRIES,
@@ -35,14 +57,15 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
 
         let message = response.data.unwrap();
         match message {
-            MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+            MessageResponse::Executed(result) => assert_eq!(result.rows_affected, 0),
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
         let name = "Ha";
         let birth_date = Utc::now();
         let message = Message::Execute(
             "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".into(),
-            vec![name.into(), birth_date.into()],
+            vec![name.into(), birth_date.into()].into(),
         );
         let response = client.sql_with_retries_and_delay(&message,
             LEADER_VACATION_RETRIES,
@@ -50,8 +73,9 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
           ).await.unwrap();
         let message = response.data.unwrap();
         match message {
-            MessageResponse::Rows(rows) => assert!(rows.len() == 0),
+            MessageResponse::Executed(result) => assert_eq!(result.rows_affected, 1),
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
 
         tm.wait_for_last_applied_log(response.log_id, 60)
@@ -62,21 +86,7 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
         //in tests , all instances share the same keys, so sqlite encryption key are
         //common to all instances.
 
-        let key_param = if tm.tls_config.is_some() {
-            let private_key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(
-                &mut File::open(&tm.key_path).unwrap(),
-            ))
-            .filter_map(|x| x.ok())
-            .next()
-            .unwrap();
-
-            let hashed_key = digest::digest(&digest::SHA256, private_key.secret_pkcs8_der());
-
-            let hashed_key = URL_SAFE.encode(hashed_key.as_ref());
-            format!("?key=\"{}\"", hashed_key)
-        } else {
-            String::new()
-        };
+        let key_param = sqlite_key_param(&tm);
 
         for (node_id, _client) in tm.clients.iter() {
             let instance = tm.tcm.instances.get(node_id).unwrap();
@@ -105,7 +115,7 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
 
         //now check query through rxqlited
 
-        let message = Message::Fetch(QUERY.into(), vec![name.into()]);
+        let message = Message::Fetch(QUERY.into(), vec![name.into()].into());
         let response = client.sql_with_retries_and_delay(&message,
             LEADER_VACATION_RETRIES,
             DELAY_BETWEEN_LEADER_VACATION_RETRIES,
@@ -121,6 +131,7 @@ fn do_simple_query(test_name: &str, tls_config: Option<TestTlsConfig>) {
                 assert_eq!(fetched_birth_date, birth_date);
             }
             MessageResponse::Error(err) => panic!("{}", err),
+            other => panic!("unexpected response: {:?}", other),
         }
     });
 }
@@ -137,3 +148,1237 @@ fn simple_query_insecure_ssl() {
         Some(TestTlsConfig::default().accept_invalid_certificates(true)),
     );
 }
+
+fn do_transaction(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      birth_date DATETIME NOT NULL
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        // the second insert violates the UNIQUE constraint: the first one must be rolled back
+        let birth_date = Utc::now();
+        let res = client
+            .transaction()
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)",
+                vec!["Ha".into(), birth_date.into()],
+            )
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)",
+                vec!["Ha".into(), birth_date.into()],
+            )
+            .commit()
+            .await;
+        assert!(res.is_err());
+        let rows = client
+            .fetch_all("SELECT name from _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 0);
+
+        let responses = client
+            .transaction()
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)",
+                vec!["Ha".into(), birth_date.into()],
+            )
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)",
+                vec!["Ho".into(), birth_date.into()],
+            )
+            .fetch_all("SELECT name from _test_user_ ORDER BY name", vec![])
+            .commit()
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 3);
+        match &responses[2] {
+            MessageResponse::Rows(rows) => {
+                assert_eq!(rows.len(), 2);
+                let name: String = rows[0].get(0);
+                assert_eq!(&name, "Ha");
+                let name: String = rows[1].get(0);
+                assert_eq!(&name, "Ho");
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn transaction() {
+    do_transaction("transaction", None);
+}
+
+fn do_batch(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      birth_date DATETIME NOT NULL
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        let birth_date = Utc::now();
+        let statements = ["Ha", "Ho", "Ha", "Hi"]
+            .iter()
+            .map(|name| {
+                (
+                    "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".to_string(),
+                    vec![(*name).into(), birth_date.into()],
+                )
+            })
+            .collect();
+        let results = client.execute_batch(statements).await.unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap().rows_affected, 1);
+        assert_eq!(results[1].as_ref().unwrap().last_insert_rowid, 2);
+        assert!(results[2].is_err());
+        assert_eq!(results[3].as_ref().unwrap().rows_affected, 1);
+
+        let rows = client
+            .fetch_all("SELECT name from _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 3);
+
+        // a statement rolling back the transaction leaves the whole batch unapplied
+        let statements = vec![
+            (
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)".to_string(),
+                vec!["Hu".into(), birth_date.into()],
+            ),
+            (
+                "INSERT OR ROLLBACK INTO _test_user_ (name,birth_date) VALUES (?,?)".to_string(),
+                vec!["Ha".into(), birth_date.into()],
+            ),
+        ];
+        assert!(client.execute_batch(statements).await.is_err());
+        let rows = client
+            .fetch_all("SELECT name from _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 3);
+
+        let result = client
+            .execute(
+                "UPDATE _test_user_ SET birth_date = ? WHERE name <> ?",
+                vec![Utc::now().into(), "Ha".into()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.rows_affected, 2);
+    });
+}
+
+#[test]
+fn batch() {
+    do_batch("batch", None);
+}
+
+fn do_column_metadata(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      nickname TEXT
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        // the header is returned even when no row matches
+        let rows = client
+            .fetch_all("SELECT id, name, nickname FROM _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 0);
+        let columns = rows.columns();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[0].name, "id");
+        assert_eq!(columns[0].decl_type.as_deref(), Some("INTEGER"));
+        assert_eq!(columns[1].name, "name");
+        assert_eq!(columns[1].decl_type.as_deref(), Some("TEXT"));
+        assert_eq!(columns[1].nullable, Some(false));
+        assert_eq!(columns[2].nullable, Some(true));
+
+        client
+            .execute(
+                "INSERT INTO _test_user_ (name,nickname) VALUES (?,?)",
+                vec!["Ha".into(), "H".into()],
+            )
+            .await
+            .unwrap();
+        let row = client
+            .fetch_one(
+                "SELECT name, nickname AS alias FROM _test_user_ WHERE name = ?",
+                vec!["Ha".into()],
+            )
+            .await
+            .unwrap();
+        let name: String = row.get_by_name("name");
+        assert_eq!(&name, "Ha");
+        let alias: String = row.try_get("alias").unwrap();
+        assert_eq!(&alias, "H");
+        assert!(row.try_get::<String, _>("nickname").is_err());
+        assert!(row.try_get::<String, _>(2).is_err());
+    });
+}
+
+#[test]
+fn column_metadata() {
+    do_column_metadata("column_metadata", None);
+}
+
+fn do_storage_classes(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_values_ (
+      id INTEGER PRIMARY KEY,
+      flag BOOLEAN,
+      amount NUMERIC,
+      big BIGINT,
+      day DATE,
+      data BLOB,
+      untyped
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        client
+            .execute(
+                "INSERT INTO _test_values_ (flag,amount,big,day,data,untyped) VALUES (?,?,?,?,?,?)",
+                vec![
+                    true.into(),
+                    3.5.into(),
+                    9_000_000_000i64.into(),
+                    "2024-01-02".into(),
+                    Value::Blob(vec![1, 2, 3]),
+                    "free".into(),
+                ],
+            )
+            .await
+            .unwrap();
+        let row = client
+            .fetch_one(
+                "SELECT flag, amount, big, day, data, untyped, 1 + 1, typeof(untyped) FROM _test_values_",
+                vec![],
+            )
+            .await
+            .unwrap();
+        // every column of the projection comes back
+        assert_eq!(row.len(), 8);
+        assert!(matches!(row[0], Value::Bool(true)));
+        assert!(matches!(row[1], Value::F64(f) if f == 3.5));
+        assert!(matches!(row[2], Value::Int(9_000_000_000)));
+        assert!(matches!(&row[3], Value::String(s) if s == "2024-01-02"));
+        assert!(matches!(&row[4], Value::Blob(b) if b == &[1, 2, 3]));
+        assert!(matches!(&row[5], Value::String(s) if s == "free"));
+        assert!(matches!(row[6], Value::Int(2)));
+        assert!(matches!(&row[7], Value::String(s) if s == "text"));
+    });
+}
+
+#[test]
+fn storage_classes() {
+    do_storage_classes("storage_classes", None);
+}
+
+fn do_nullable_columns(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      nickname TEXT,
+      birth_date DATETIME
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        client
+            .execute(
+                "INSERT INTO _test_user_ (name,nickname,birth_date) VALUES (?,?,?)",
+                vec!["Ha".into(), Value::Null, None::<DateTime<Utc>>.into()],
+            )
+            .await
+            .unwrap();
+        let row = client
+            .fetch_one(
+                "SELECT nickname, birth_date FROM _test_user_ WHERE name = ?",
+                vec!["Ha".into()],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(row[0], Value::Null));
+        assert!(matches!(row[1], Value::Null));
+
+        client
+            .execute(
+                "UPDATE _test_user_ SET nickname = ? WHERE name = ?",
+                vec![Some("H").into(), "Ha".into()],
+            )
+            .await
+            .unwrap();
+        let row = client
+            .fetch_one(
+                "SELECT nickname FROM _test_user_ WHERE name = ?",
+                vec!["Ha".into()],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(&row[0], Value::String(s) if s == "H"));
+
+        // NULL never compares equal, even to a NULL parameter
+        let rows = client
+            .fetch_all(
+                "SELECT name FROM _test_user_ WHERE birth_date = ?",
+                vec![Value::Null],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 0);
+        let rows = client
+            .fetch_all(
+                "SELECT name FROM _test_user_ WHERE birth_date IS ?",
+                vec![Value::Null],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+    });
+}
+
+#[test]
+fn nullable_columns() {
+    do_nullable_columns("nullable_columns", None);
+}
+
+fn do_strict_accessors(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      uid BLOB NOT NULL,
+      name TEXT NOT NULL,
+      nickname TEXT,
+      profile TEXT NOT NULL,
+      score INTEGER NOT NULL
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        let uid = uuid::Uuid::from_u128(0x1234_5678_9abc_def0_1234_5678_9abc_def0);
+        client
+            .execute(
+                "INSERT INTO _test_user_ (uid,name,nickname,profile,score) VALUES (?,?,?,?,?)",
+                vec![
+                    uid.as_bytes().to_vec().into(),
+                    "Ha".into(),
+                    Value::Null,
+                    r#"{"lang":"rust"}"#.into(),
+                    (-40000i64).into(),
+                ],
+            )
+            .await
+            .unwrap();
+        let row = client
+            .fetch_one(
+                "SELECT uid, name, nickname, profile, score FROM _test_user_",
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(row.try_get::<uuid::Uuid, _>("uid").unwrap(), uid);
+        assert_eq!(row.try_get::<Vec<u8>, _>("uid").unwrap(), uid.as_bytes());
+        assert_eq!(row.try_get::<Option<String>, _>("nickname").unwrap(), None);
+        assert_eq!(
+            row.try_get::<serde_json::Value, _>("profile").unwrap()["lang"],
+            "rust"
+        );
+        assert_eq!(row.try_get::<i64, _>("score").unwrap(), -40000);
+
+        // mismatches are reported instead of being coerced
+        match row.try_get::<String, _>("nickname") {
+            Err(RowError::Decode { index, source }) => {
+                assert_eq!(index, 2);
+                assert_eq!(source, ValueError::UnexpectedNull);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            row.try_get::<i64, _>("name"),
+            Err(RowError::Decode {
+                source: ValueError::Mismatch { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            row.try_get::<i16, _>("score"),
+            Err(RowError::Decode {
+                source: ValueError::OutOfRange { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            row.try_get::<u64, _>("score"),
+            Err(RowError::Decode {
+                source: ValueError::OutOfRange { .. },
+                ..
+            })
+        ));
+        assert!(row.try_get::<uuid::Uuid, _>("name").is_err());
+
+        // and so are lossy conversions
+        assert!(Value::Bool(true).try_get::<i64>().is_err());
+        assert!(Value::F64(0.1).try_get::<f32>().is_err());
+        assert_eq!(Value::F64(0.5).try_get::<f32>().unwrap(), 0.5);
+        assert!(Value::Int((1 << 53) + 1).try_get::<f64>().is_err());
+        assert!(Value::Int(i64::MAX).try_get::<f64>().is_err());
+        assert_eq!(Value::Int(1 << 53).try_get::<f64>().unwrap(), 9007199254740992.0);
+    });
+}
+
+#[test]
+fn strict_accessors() {
+    do_strict_accessors("strict_accessors", None);
+}
+
+#[derive(serde::Deserialize, Debug, PartialEq)]
+struct TestUser {
+    id: i64,
+    name: String,
+    nickname: Option<String>,
+    birth_date: DateTime<Utc>,
+}
+
+fn do_fetch_as(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      nickname TEXT,
+      birth_date DATETIME NOT NULL
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        let birth_date = Utc::now();
+        for (name, nickname) in [("Ha", Some("H")), ("Ho", None)] {
+            client
+                .execute(
+                    "INSERT INTO _test_user_ (name,nickname,birth_date) VALUES (?,?,?)",
+                    vec![name.into(), nickname.into(), birth_date.into()],
+                )
+                .await
+                .unwrap();
+        }
+
+        let users: Vec<TestUser> = client
+            .fetch_as("SELECT * FROM _test_user_ ORDER BY name", vec![])
+            .await
+            .unwrap();
+        assert_eq!(
+            users,
+            vec![
+                TestUser {
+                    id: 1,
+                    name: "Ha".into(),
+                    nickname: Some("H".into()),
+                    birth_date,
+                },
+                TestUser {
+                    id: 2,
+                    name: "Ho".into(),
+                    nickname: None,
+                    birth_date,
+                },
+            ]
+        );
+
+        let (id, name): (i64, String) = client
+            .fetch_one_as(
+                "SELECT id, name FROM _test_user_ WHERE name = ?",
+                vec!["Ho".into()],
+            )
+            .await
+            .unwrap();
+        assert_eq!((id, name.as_str()), (2, "Ho"));
+
+        let count: i64 = client
+            .fetch_one_as("SELECT count(*) FROM _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let user: Option<TestUser> = client
+            .fetch_optional_as(
+                "SELECT * FROM _test_user_ WHERE name = ?",
+                vec!["Hi".into()],
+            )
+            .await
+            .unwrap();
+        assert!(user.is_none());
+
+        // a column missing from the projection is reported
+        let res: Result<Vec<TestUser>, _> = client
+            .fetch_as("SELECT id, name FROM _test_user_", vec![])
+            .await;
+        assert!(res.is_err());
+    });
+}
+
+#[test]
+fn fetch_as() {
+    do_fetch_as("fetch_as", None);
+}
+
+fn do_named_parameters(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::new(test_name, 3, tls_config);
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE,
+      nickname TEXT,
+      birth_date DATETIME NOT NULL
+      )"
+            .into(),
+            vec![].into(),
+        );
+        let response = client
+            .sql_with_retries_and_delay(
+                &message,
+                LEADER_VACATION_RETRIES,
+                DELAY_BETWEEN_LEADER_VACATION_RETRIES,
+            )
+            .await
+            .unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+
+        let mut params: BTreeMap<String, Value> = BTreeMap::new();
+        params.insert("birth_date".into(), Utc::now().into());
+        params.insert("name".into(), "Ha".into());
+        params.insert("@nickname".into(), "Ha's :name".into());
+        let result = client
+            .execute(
+                "INSERT INTO _test_user_ (birth_date,name,nickname) VALUES ($birth_date,:name,@nickname)",
+                params,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.rows_affected, 1);
+
+        // a name used twice is bound once
+        let mut params: BTreeMap<String, Value> = BTreeMap::new();
+        params.insert("name".into(), "Ha".into());
+        let row = client
+            .fetch_one(
+                "SELECT nickname FROM _test_user_ WHERE name = :name AND nickname <> :name",
+                params.clone(),
+            )
+            .await
+            .unwrap();
+        let nickname: String = row.get(0);
+        assert_eq!(&nickname, "Ha's :name");
+
+        // and so is a name given with different prefixes
+        let rows = client
+            .fetch_all(
+                "SELECT * FROM _test_user_ WHERE name = :name AND name = @name",
+                params.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+
+        params.insert("typo".into(), "Ha".into());
+        assert!(client
+            .fetch_all("SELECT * FROM _test_user_ WHERE name = :name", params)
+            .await
+            .is_err());
+        assert!(client
+            .fetch_all(
+                "SELECT * FROM _test_user_ WHERE name = :name",
+                BTreeMap::<String, Value>::new(),
+            )
+            .await
+            .is_err());
+    });
+}
+
+#[test]
+fn named_parameters() {
+    do_named_parameters("named_parameters", None);
+}
+
+fn do_read_write_classification(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &[],
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE
+      )",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        // reads are answered by the node without a raft log entry
+        for sql in [
+            "PRAGMA table_info(_test_user_)",
+            "EXPLAIN QUERY PLAN SELECT * FROM _test_user_",
+            "WITH names AS (SELECT name FROM _test_user_) SELECT * FROM names",
+            "SELECT 1; SELECT name FROM _test_user_",
+        ] {
+            let response = client
+                .consistent_sql(&Message::Fetch(sql.into(), vec![].into()))
+                .await
+                .unwrap();
+            if let Some(MessageResponse::Error(err)) = &response.data {
+                panic!("{}: {}", sql, err);
+            }
+            assert_eq!(response.log_id.index, u64::MAX, "{}", sql);
+        }
+
+        for sql in [
+            "INSERT INTO _test_user_ (name) VALUES ('Ha')",
+            "WITH ids AS (SELECT 1 AS id) DELETE FROM _test_user_ WHERE id IN (SELECT id FROM ids)",
+            "SELECT 1; INSERT INTO _test_user_ (name) VALUES ('Ha')",
+        ] {
+            let response = client
+                .consistent_sql(&Message::Execute(sql.into(), vec![].into()))
+                .await
+                .unwrap();
+            if let Some(MessageResponse::Error(err)) = &response.data {
+                panic!("{}: {}", sql, err);
+            }
+            assert_ne!(response.log_id.index, u64::MAX, "{}", sql);
+        }
+    });
+}
+
+#[test]
+fn read_write_classification() {
+    do_read_write_classification("read_write_classification", None);
+}
+
+fn do_non_deterministic_writes(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &[],
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL,
+      birth_date DATETIME NOT NULL
+      )",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        for sql in [
+            "INSERT INTO _test_user_ (name,birth_date) VALUES (hex(randomblob(4)),?)",
+            "INSERT INTO _test_user_ (name,birth_date) VALUES ('Ha',datetime('now'))",
+            "INSERT INTO _test_user_ (name,birth_date) VALUES ('Ha',CURRENT_TIMESTAMP)",
+        ] {
+            let params: Vec<Value> = if sql.contains('?') {
+                vec![Utc::now().into()]
+            } else {
+                vec![]
+            };
+            let err = client.execute(sql, params).await.unwrap_err();
+            assert!(err.to_string().contains("non-deterministic"), "{}", err);
+        }
+        // the value is computed on the client
+        client
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES ('Ha',datetime(?))",
+                vec![Utc::now().into()],
+            )
+            .await
+            .unwrap();
+        // reads aren't replicated
+        client
+            .fetch_one("SELECT datetime('now'), random()", vec![])
+            .await
+            .unwrap();
+
+        // defaults run when a row is inserted, triggers when they fire
+        client
+            .execute(
+                "CREATE TABLE IF NOT EXISTS _test_log_ (
+      id INTEGER PRIMARY KEY,
+      message TEXT NOT NULL,
+      created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+      )",
+                vec![],
+            )
+            .await
+            .unwrap();
+        let err = client
+            .execute("INSERT INTO _test_log_ (message) VALUES ('Ha')", vec![])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("non-deterministic"), "{}", err);
+        client
+            .execute(
+                "INSERT INTO _test_log_ (message,created_at) VALUES ('Ha',?)",
+                vec![Utc::now().into()],
+            )
+            .await
+            .unwrap();
+        client
+            .execute(
+                "CREATE TRIGGER IF NOT EXISTS _test_user_log_ AFTER DELETE ON _test_user_
+      BEGIN INSERT INTO _test_log_ (message,created_at) VALUES (old.name,random()); END",
+                vec![],
+            )
+            .await
+            .unwrap();
+        let err = client
+            .execute("DELETE FROM _test_user_", vec![])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("non-deterministic"), "{}", err);
+        client
+            .execute("DROP TRIGGER _test_user_log_", vec![])
+            .await
+            .unwrap();
+
+        let options = RequestOptions::default().allow_non_deterministic(true);
+        client
+            .execute_with_options(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES ('Ha',datetime('now'))",
+                vec![],
+                &options,
+            )
+            .await
+            .unwrap();
+        assert!(client
+            .transaction()
+            .execute("DELETE FROM _test_user_ WHERE id = random()", vec![])
+            .commit()
+            .await
+            .is_err());
+        client
+            .transaction()
+            .execute("DELETE FROM _test_user_ WHERE id = random()", vec![])
+            .options(options)
+            .commit()
+            .await
+            .unwrap();
+    });
+}
+
+#[test]
+fn non_deterministic_writes() {
+    do_non_deterministic_writes("non_deterministic_writes", None);
+}
+
+fn do_changeset_writes(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        // schema changes are replicated as statements
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &["--write-mode", "changeset"],
+            TEST_USER_TABLE,
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        // the leader computes the values, the other nodes get its changes
+        let message = Message::Execute(
+            "INSERT INTO _test_user_ (name,birth_date) VALUES (hex(randomblob(8)),datetime('now'))"
+                .into(),
+            vec![].into(),
+        );
+        let response = client.sql(&message).await.unwrap();
+        assert_ne!(response.log_id.index, u64::MAX);
+        let result = expect_executed(response);
+        assert_eq!(result.rows_affected, 1);
+        assert_eq!(result.last_insert_rowid, 1);
+        let responses = client
+            .transaction()
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (hex(randomblob(8)),?)",
+                vec![Utc::now().into()],
+            )
+            .execute(
+                "UPDATE _test_user_ SET birth_date = datetime('now') WHERE id = abs(random()) % 2 + 1",
+                vec![],
+            )
+            .commit()
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 2);
+
+        // a write that fails changes nothing and isn't replicated
+        let message = Message::Execute(
+            "INSERT INTO _test_user_ (id,name,birth_date) VALUES (1,'Ha',datetime('now'))".into(),
+            vec![].into(),
+        );
+        let response = client.sql(&message).await.unwrap();
+        assert_eq!(response.log_id.index, u64::MAX);
+        assert!(matches!(response.data, Some(MessageResponse::Error(_))));
+
+        // sessions don't record the rows of a table without a primary key
+        client
+            .execute("CREATE TABLE IF NOT EXISTS _test_log_ (message TEXT)", vec![])
+            .await
+            .unwrap();
+        client
+            .execute("INSERT INTO _test_log_ (message) VALUES ('Ha')", vec![])
+            .await
+            .unwrap();
+        let err = client
+            .execute(
+                "INSERT INTO _test_log_ (message) VALUES (hex(randomblob(8)))",
+                vec![],
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("non-deterministic"), "{}", err);
+
+        let message = Message::Fetch("SELECT COUNT(*) FROM _test_log_".into(), vec![].into());
+        let rows = expect_rows(client.sql(&message).await.unwrap());
+        assert_eq!(rows[0].get::<i64>(0), 1);
+
+        // every node ends up with the values the leader computed
+        let metrics = tm.get_metrics(1).await.unwrap();
+        tm.wait_for_last_applied_log(metrics.last_applied.unwrap(), 60)
+            .await
+            .unwrap();
+        let key_param = sqlite_key_param(&tm);
+        let mut users: Option<Vec<(i64, String, String)>> = None;
+        for (node_id, _client) in tm.clients.iter() {
+            let instance = tm.tcm.instances.get(node_id).unwrap();
+            let sqlite_db_path =
+                PathBuf::from(&instance.data_path).join(format!("sqlite.db{}", key_param));
+            let pool = Pool::<Sqlite>::connect(sqlite_db_path.to_str().unwrap())
+                .await
+                .unwrap();
+            let node_users: Vec<(i64, String, String)> =
+                sqlx::query_as("SELECT id,name,birth_date FROM _test_user_ ORDER BY id")
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap();
+            assert_eq!(node_users.len(), 2);
+            match users.as_ref() {
+                Some(users) => assert_eq!(users, &node_users, "node {}", node_id),
+                None => users = Some(node_users),
+            }
+        }
+    });
+}
+
+#[test]
+fn changeset_writes() {
+    do_changeset_writes("changeset_writes", None);
+}
+
+fn do_bounded_staleness_reads(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &[],
+            "CREATE TABLE IF NOT EXISTS _test_user_ (
+      id INTEGER PRIMARY KEY,
+      name TEXT NOT NULL UNIQUE
+      )",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+        let follower = tm.clients.get(&2).unwrap();
+
+        let message = Message::Execute(
+            "INSERT INTO _test_user_ (name) VALUES (?)".into(),
+            vec!["Ha".into()].into(),
+        );
+        let response = client.sql(&message).await.unwrap();
+        if let MessageResponse::Error(err) = response.data.unwrap() {
+            panic!("{}", err);
+        }
+        let write_index = response.log_id.index;
+
+        let query = Message::Fetch(
+            "SELECT name FROM _test_user_ WHERE name = ?".into(),
+            vec!["Ha".into()].into(),
+        );
+        // read your writes on a follower
+        for options in [
+            RequestOptions::default().min_applied_index(write_index),
+            RequestOptions::default().max_staleness(std::time::Duration::from_secs(1)),
+        ] {
+            let response = follower
+                .node_sql_with_options(&query, &options)
+                .await
+                .unwrap();
+            // answered by the follower itself
+            assert_eq!(response.log_id.index, u64::MAX);
+            assert_eq!(expect_rows(response).len(), 1);
+        }
+
+        // a log entry that doesn't exist yet
+        let options = RequestOptions::default().min_applied_index(write_index + 1000);
+        let response = follower
+            .node_sql_with_options(&query, &options)
+            .await
+            .unwrap();
+        match response.data.unwrap() {
+            MessageResponse::Error(err) => {
+                assert_eq!(err.kind, ErrorKind::NotCaughtUp, "{}", err);
+                assert!(err.message.contains("hasn't applied"), "{}", err);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn bounded_staleness_reads() {
+    do_bounded_staleness_reads("bounded_staleness_reads", None);
+}
+
+/// The value of `_test_value_` a `sql-consistent` read gets through `client`, once a node
+/// answers it.
+#[cfg(target_os = "linux")]
+async fn consistent_value(client: &RXQLiteClient) -> i64 {
+    let query = Message::Fetch(
+        "SELECT value FROM _test_value_ WHERE id = 1".into(),
+        vec![].into(),
+    );
+    for _ in 0..60 {
+        // the former leader answers no leader until it learns the new one
+        if let Ok(response) = client.consistent_sql(&query).await {
+            return expect_rows(response)[0].get(0);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    }
+    panic!("no node answered the read");
+}
+
+#[cfg(target_os = "linux")]
+fn do_lease_reads(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &["--read-mode", "lease"],
+            "CREATE TABLE IF NOT EXISTS _test_value_ (id INTEGER PRIMARY KEY, value INTEGER)",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "INSERT INTO _test_value_ (id, value) VALUES (1, 1)".into(),
+            vec![].into(),
+        );
+        sql_with_retries(client, &message).await;
+        // the first read takes the lease the next ones are answered with
+        for _ in 0..3 {
+            assert_eq!(consistent_value(client).await, 1);
+        }
+
+        // cut the leader off until the others elect a new one
+        let leader_id = tm.get_metrics(1).await.unwrap().current_leader.unwrap();
+        tm.suspend(leader_id).unwrap();
+        let (follower_id, follower) = tm
+            .clients
+            .iter()
+            .find(|(node_id, _)| **node_id != leader_id)
+            .unwrap();
+        let mut new_leader_id = None;
+        for _ in 0..60 {
+            if let Ok(metrics) = follower.node_metrics().await {
+                if metrics
+                    .current_leader
+                    .is_some_and(|node_id| node_id != leader_id)
+                {
+                    new_leader_id = metrics.current_leader;
+                    break;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+        assert!(
+            new_leader_id.is_some(),
+            "node {} saw no new leader",
+            follower_id
+        );
+        let message = Message::Execute(
+            "UPDATE _test_value_ SET value = 2 WHERE id = 1".into(),
+            vec![].into(),
+        );
+        sql_with_retries(follower, &message).await;
+
+        // the lease of the former leader expired while it was cut off: it must not answer
+        // with the value it has
+        tm.resume(leader_id).unwrap();
+        let former_leader = tm.clients.get(&leader_id).unwrap();
+        assert_eq!(consistent_value(former_leader).await, 2);
+        assert_eq!(consistent_value(follower).await, 2);
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lease_reads() {
+    do_lease_reads("lease_reads", None);
+}
+
+fn do_forwarded_reads(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            &["--forward-reads"],
+            "CREATE TABLE IF NOT EXISTS _test_value_ (id INTEGER PRIMARY KEY, value INTEGER)",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        let message = Message::Execute(
+            "INSERT INTO _test_value_ (id, value) VALUES (1, 1)".into(),
+            vec![].into(),
+        );
+        expect_executed(client.sql(&message).await.unwrap());
+
+        let leader_id = tm.get_metrics(1).await.unwrap().current_leader.unwrap();
+        let (follower_id, follower) = tm
+            .clients
+            .iter()
+            .find(|(node_id, _)| **node_id != leader_id)
+            .unwrap();
+        let query = Message::Fetch(
+            "SELECT value FROM _test_value_ WHERE id = 1".into(),
+            vec![].into(),
+        );
+        let rows = expect_rows(follower.consistent_sql(&query).await.unwrap());
+        assert_eq!(rows[0].get::<i64>(0), 1);
+        // the follower answered, the client wasn't redirected to the leader
+        assert_eq!(follower.leader.lock().unwrap().0, *follower_id);
+    });
+}
+
+#[test]
+fn forwarded_reads() {
+    do_forwarded_reads("forwarded_reads", None);
+}
+
+#[test]
+fn forwarded_reads_insecure_ssl() {
+    do_forwarded_reads(
+        "forwarded_reads_insecure_ssl",
+        Some(TestTlsConfig::default().accept_invalid_certificates(true)),
+    );
+}
+
+fn do_typed_errors(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(test_name, tls_config, &[], TEST_USER_TABLE).await;
+        let client = tm.clients.get(&1).unwrap();
+
+        let insert = "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)";
+        client
+            .execute(insert, vec!["Ha".into(), Utc::now().into()])
+            .await
+            .unwrap();
+        let err = client
+            .execute(insert, vec!["Ha".into(), Utc::now().into()])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Constraint(ConstraintKind::Unique),
+            "{}",
+            err
+        );
+        // SQLITE_CONSTRAINT_UNIQUE
+        assert_eq!(err.code, Some(2067));
+        assert!(!err.is_retryable());
+
+        let err = client
+            .execute(
+                "INSERT INTO _test_user_ (name) VALUES (?)",
+                vec!["Hu".into()],
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::Constraint(ConstraintKind::NotNull),
+            "{}",
+            err
+        );
+
+        let err = client
+            .fetch_all("SELECT * FROM _test_missing_", vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Sql, "{}", err);
+        let err = client
+            .execute("CREATE TABLE _test_user_ (id INTEGER PRIMARY KEY)", vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Sql, "{}", err);
+
+        let err = client
+            .execute(
+                "INSERT INTO _test_user_ (name,birth_date) VALUES (?,datetime('now'))",
+                vec!["Hi".into()],
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidRequest, "{}", err);
+    });
+}
+
+#[test]
+fn typed_errors() {
+    do_typed_errors("typed_errors", None);
+}
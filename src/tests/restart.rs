@@ -0,0 +1,80 @@
+use super::*;
+use crate::Node;
+
+fn do_restart_with_new_addresses(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let mut tm = TestManager::new(test_name, 3, tls_config.clone());
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let leader = tm.get_metrics(1).await.unwrap().current_leader.unwrap();
+        let node_id = (1..=3).find(|node_id| *node_id != leader).unwrap();
+
+        // the node restarts at the addresses of its config file
+        tm.kill(node_id).unwrap();
+        let port = PORT_MANAGER.get_or_init(Default::default).reserve(3);
+        let node = Node {
+            api_addr: format!("127.0.0.1:{}", port),
+            rpc_addr: format!("127.0.0.1:{}", port + 1),
+            compressed_snapshots: true,
+        };
+        let config_path = tm.working_directory.join(format!("node-{}.toml", node_id));
+        std::fs::write(
+            &config_path,
+            format!(
+                "http_addr = \"{}\"\nrpc_addr = \"{}\"\nnotifications_addr = \"127.0.0.1:{}\"\n",
+                node.api_addr,
+                node.rpc_addr,
+                port + 2
+            ),
+        )
+        .unwrap();
+        tm.start_with_args(node_id, &["--config", config_path.to_str().unwrap()])
+            .unwrap();
+        tm.instances.get_mut(&node_id).unwrap().http_addr = node.api_addr.clone();
+        let client = RXQLiteClientBuilder::new(node_id, node.api_addr.clone())
+            .use_tls(tls_config.is_some())
+            .accept_invalid_certificates(
+                tls_config
+                    .as_ref()
+                    .map(|tls_config| tls_config.accept_invalid_certificates)
+                    .unwrap_or(false),
+            )
+            .build();
+        tm.clients.insert(node_id, client);
+
+        // the membership of the cluster learns them
+        let mut updated = false;
+        for _ in 0..30 {
+            if let Ok(metrics) = tm.clients.get(&leader).unwrap().node_metrics().await {
+                let membership = metrics.membership_config.membership();
+                if membership.get_node(&node_id) == Some(&node) {
+                    updated = true;
+                    break;
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        assert!(updated, "the addresses of node {} weren't updated", node_id);
+
+        // the leader replicates to the node at its new address
+        let client = tm.clients.get(&leader).unwrap();
+        let message = Message::Execute(TEST_USER_TABLE.into(), vec![].into());
+        let response = sql_with_retries(client, &message).await;
+        tm.wait_for_last_applied_log(response.log_id, 60)
+            .await
+            .unwrap();
+    });
+}
+
+#[test]
+fn restart_with_new_addresses() {
+    do_restart_with_new_addresses("restart_with_new_addresses", None);
+}
+
+#[test]
+fn restart_with_new_addresses_insecure_ssl() {
+    do_restart_with_new_addresses(
+        "restart_with_new_addresses_insecure_ssl",
+        Some(TestTlsConfig::default().accept_invalid_certificates(true)),
+    );
+}
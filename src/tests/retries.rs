@@ -0,0 +1,174 @@
+use super::*;
+use crate::{RequestIds, RetryPolicy};
+use rxqlite_common::RequestOptions;
+
+fn do_leader_discovery(test_name: &str, tls_config: Option<TestTlsConfig>) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let mut tm = TestManager::new(test_name, 3, tls_config.clone());
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+
+        // a client of node 1 that only knows of node 2
+        let addresses: HashMap<NodeId, String> = tm
+            .instances
+            .iter()
+            .map(|(node_id, instance)| (*node_id, instance.http_addr.clone()))
+            .collect();
+        let (leader_host, leader_port) = addresses[&1]
+            .split_once(':')
+            .map(|(host, port)| (host.to_string(), port.parse().unwrap()))
+            .unwrap();
+        let options = crate::ConnectOptions {
+            leader_id: 1,
+            leader_host,
+            leader_port,
+            tls_config: tls_config.map(|tls_config| rxqlite_common::RSQliteClientTlsConfig {
+                accept_invalid_certificates: tls_config.accept_invalid_certificates,
+                ..Default::default()
+            }),
+            seeds: vec![addresses[&2].clone()],
+            ..Default::default()
+        };
+        let client = options.connect().await.unwrap();
+
+        let message = Message::Execute(
+            "CREATE TABLE IF NOT EXISTS _test_value_ (id INTEGER PRIMARY KEY, value INTEGER)"
+                .into(),
+            vec![].into(),
+        );
+        sql_with_retries(&client, &message).await;
+
+        tm.kill(1).unwrap();
+        // the other nodes elect a leader, which the client finds through node 2
+        let message = Message::Execute(
+            "INSERT INTO _test_value_ (id, value) VALUES (1, 1)".into(),
+            vec![].into(),
+        );
+        let mut response = None;
+        for _ in 0..60 {
+            if let Ok(res) = client.sql(&message).await {
+                response = Some(res);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+        assert_eq!(expect_executed(response.unwrap()).rows_affected, 1);
+        assert_ne!(client.leader.lock().unwrap().0, 1);
+        // node 3 was learned from the membership of the cluster
+        assert!(client.nodes.lock().unwrap().contains(&addresses[&3]));
+    });
+}
+
+#[test]
+fn leader_discovery() {
+    do_leader_discovery("leader_discovery", None);
+}
+
+#[test]
+fn retry_policy() {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        // a port no node listens on
+        let port = PORT_MANAGER.get_or_init(Default::default).reserve(1);
+        let message = Message::Fetch("SELECT 1".into(), vec![].into());
+
+        let client = RXQLiteClientBuilder::new(1, format!("127.0.0.1:{}", port))
+            .retry_policy(
+                RetryPolicy::default()
+                    .max_attempts(3)
+                    .backoff(
+                        std::time::Duration::from_millis(200),
+                        std::time::Duration::from_millis(200),
+                    )
+                    .jitter(false),
+            )
+            .build();
+        let start = std::time::Instant::now();
+        match client.sql(&message).await {
+            Err(openraft::error::RPCError::Unreachable(_)) => {}
+            other => panic!("unexpected response: {:?}", other),
+        }
+        // two backoffs between three attempts
+        assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+
+        let client = RXQLiteClientBuilder::new(1, format!("127.0.0.1:{}", port))
+            .retry_policy(
+                RetryPolicy::default()
+                    .max_attempts(usize::MAX)
+                    .deadline(Some(std::time::Duration::from_secs(1))),
+            )
+            .build();
+        let start = std::time::Instant::now();
+        assert!(client.sql(&message).await.is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+        let client = RXQLiteClientBuilder::new(1, format!("127.0.0.1:{}", port))
+            .retry_policy(RetryPolicy::no_retry())
+            .build();
+        let start = std::time::Instant::now();
+        assert!(client.sql(&message).await.is_err());
+        assert!(start.elapsed() < std::time::Duration::from_millis(200));
+    });
+}
+
+fn do_idempotent_writes(test_name: &str, tls_config: Option<TestTlsConfig>, args: &[&str]) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let tm = TestManager::with_table(
+            test_name,
+            tls_config,
+            args,
+            "CREATE TABLE IF NOT EXISTS _test_visit_ (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+        )
+        .await;
+        let client = tm.clients.get(&1).unwrap();
+
+        // a write sent again with the same id is answered without being applied again
+        let request_ids = RequestIds::new();
+        let options = RequestOptions::default().request_id(request_ids.next_id());
+        let insert = "INSERT INTO _test_visit_ (name) VALUES ('Ha')";
+        let first = client
+            .execute_with_options(insert, vec![], &options)
+            .await
+            .unwrap();
+        assert_eq!(first.rows_affected, 1);
+        let again = client
+            .execute_with_options(insert, vec![], &options)
+            .await
+            .unwrap();
+        assert_eq!(again, first);
+
+        let options = RequestOptions::default().request_id(request_ids.next_id());
+        let next = client
+            .execute_with_options(insert, vec![], &options)
+            .await
+            .unwrap();
+        assert_eq!(next.last_insert_rowid, first.last_insert_rowid + 1);
+
+        let message = Message::Fetch("SELECT COUNT(*) FROM _test_visit_".into(), vec![].into());
+        let rows = expect_rows(client.sql(&message).await.unwrap());
+        assert_eq!(rows[0].get::<i64>(0), 2);
+
+        // ids are passed in the options, not in the message
+        let message = Message::WithRequestId {
+            request_id: request_ids.next_id(),
+            message: Box::new(Message::Execute(insert.into(), vec![].into())),
+        };
+        let response = client.sql(&message).await.unwrap();
+        assert!(matches!(response.data, Some(MessageResponse::Error(_))));
+    });
+}
+
+#[test]
+fn idempotent_writes() {
+    do_idempotent_writes("idempotent_writes", None, &[]);
+}
+
+#[test]
+fn idempotent_changeset_writes() {
+    do_idempotent_writes(
+        "idempotent_changeset_writes",
+        None,
+        &["--write-mode", "changeset"],
+    );
+}
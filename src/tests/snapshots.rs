@@ -0,0 +1,104 @@
+use super::*;
+use sqlx::types::chrono::Utc;
+
+fn do_snapshots(test_name: &str, tls_config: Option<TestTlsConfig>, args: &[&str]) {
+    let rt = Runtime::new().unwrap();
+    let _ = rt.block_on(async {
+        let mut tm = TestManager::with_table(test_name, tls_config, args, TEST_USER_TABLE).await;
+        let client = tm.clients.get(&1).unwrap();
+        let insert = "INSERT INTO _test_user_ (name,birth_date) VALUES (?,?)";
+        for i in 0..100 {
+            client
+                .execute(insert, vec![format!("Ha{}", i).into(), Utc::now().into()])
+                .await
+                .unwrap();
+        }
+
+        // every node tells the cluster it reads compressed snapshots
+        let mut told = false;
+        for _ in 0..30 {
+            let metrics = tm.get_metrics(1).await.unwrap();
+            let membership = metrics.membership_config.membership();
+            if membership
+                .nodes()
+                .all(|(_, node)| node.compressed_snapshots)
+            {
+                told = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        assert!(told, "the nodes didn't tell they read compressed snapshots");
+
+        client.trigger_snapshot().await.unwrap();
+        let mut snapshot = None;
+        for _ in 0..30 {
+            let metrics = tm.get_metrics(1).await.unwrap();
+            if metrics.snapshot.is_some() {
+                snapshot = Some(metrics);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        let metrics = snapshot.expect("the snapshot wasn't built");
+        let leader = metrics.current_leader.unwrap();
+
+        // the snapshot is a single file, the copy of the database it was made from is gone
+        let data_path = &tm.tcm.instances.get(&leader).unwrap().data_path;
+        let snapshots: Vec<_> = std::fs::read_dir(data_path.join("snapshots"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(snapshots.len(), 1, "{:?}", snapshots);
+        assert!(snapshots[0].ends_with(".snap"), "{:?}", snapshots);
+        assert!(!data_path.join("sqlite.db.snapshot").exists());
+        // the version of the layout follows the magic bytes, 2 for compressed snapshots
+        let file = std::fs::read(data_path.join("snapshots").join(&snapshots[0])).unwrap();
+        assert_eq!(file[8..12] == 2_u32.to_be_bytes(), !args.is_empty());
+
+        // the node keeps serving requests
+        client
+            .execute(insert, vec!["Hu".into(), Utc::now().into()])
+            .await
+            .unwrap();
+
+        // the nodes restart from the snapshot, then apply the logs after it, reading the
+        // snapshot whatever the compression they restart with
+        tm.kill_all().unwrap();
+        #[cfg(target_os = "linux")]
+        {
+            tokio::time::sleep(DELAY_BETWEEN_KILL_AND_START).await;
+        }
+        tm.start().unwrap();
+        tm.wait_for_cluster_established(1, 60).await.unwrap();
+        let client = tm.clients.get(&1).unwrap();
+        let rows = client
+            .fetch_all("SELECT name from _test_user_", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 101);
+    });
+}
+
+#[test]
+fn snapshots() {
+    do_snapshots("snapshots", None, &[]);
+}
+
+#[test]
+fn compressed_snapshots() {
+    do_snapshots(
+        "compressed_snapshots",
+        None,
+        &["--snapshot-compression", "zstd"],
+    );
+}
+
+#[test]
+fn snapshots_insecure_ssl() {
+    do_snapshots(
+        "snapshots_insecure_ssl",
+        Some(TestTlsConfig::default().accept_invalid_certificates(true)),
+        &[],
+    );
+}